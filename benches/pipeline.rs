@@ -0,0 +1,113 @@
+//! Times parsing, fetching, and merging over local fixture feeds, so the numbers
+//! don't depend on the network.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use dashboard_feeds::{
+    document_items, fetch_feed, merge_and_sort, FeedItem, FeedSpec, FetchOptions, MergeOptions,
+};
+use reqwest::{Client, Url};
+use reqwest_middleware::ClientBuilder;
+use syndication::Feed;
+
+/// How many feeds the merge benchmark combines.
+const FEEDS: usize = 50;
+
+/// An RSS feed with this many items, a day apart.
+fn large_feed(items: usize) -> String {
+    let mut body = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>Large</title>
+    <link>https://example.com/</link>
+    <description>A feed with many items</description>
+"#,
+    );
+    for i in 0..items {
+        body.push_str(&format!(
+            "    <item>\n      <title>Post {i}</title>\n      <link>https://example.com/{i}</link>\n      \
+             <guid>https://example.com/{i}</guid>\n      <pubDate>{}</pubDate>\n      \
+             <description>Summary of post {i}</description>\n    </item>\n",
+            chrono::DateTime::from_timestamp(1_750_000_000 - i as i64 * 86_400, 0)
+                .unwrap()
+                .to_rfc2822()
+        ));
+    }
+    body.push_str("  </channel>\n</rss>\n");
+    body
+}
+
+fn feed_spec(i: usize) -> FeedSpec {
+    FeedSpec::new(&Url::parse(&format!("https://example.com/{i}/feed.xml")).unwrap())
+}
+
+/// The items of [`FEEDS`] copies of a feed, as if each came from a different site.
+fn batches(body: &str) -> Vec<Vec<FeedItem>> {
+    let document = body.parse::<Feed>().unwrap();
+    (0..FEEDS)
+        .map(|i| document_items(&document, &feed_spec(i), i, None, &FetchOptions::default()))
+        .collect()
+}
+
+fn parse(c: &mut Criterion) {
+    let large = large_feed(500);
+    let fixtures = [
+        ("large", large.as_str()),
+        (
+            "rss-with-atom-links",
+            include_str!("../tests/fixtures/rss-with-atom-links.xml"),
+        ),
+        (
+            "atom-with-rss-dates",
+            include_str!("../tests/fixtures/atom-with-rss-dates.xml"),
+        ),
+    ];
+
+    let mut group = c.benchmark_group("parse");
+    for (name, body) in fixtures {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let document = black_box(body).parse::<Feed>().unwrap();
+                document_items(&document, &feed_spec(0), 0, None, &FetchOptions::default())
+            })
+        });
+    }
+    group.finish();
+}
+
+fn fetch(c: &mut Criterion) {
+    let path =
+        std::env::temp_dir().join(format!("dashboard-feeds-bench-{}.xml", std::process::id()));
+    std::fs::write(&path, large_feed(500)).unwrap();
+    let feed = FeedSpec::new(&Url::from_file_path(&path).unwrap());
+    let client = ClientBuilder::new(Client::new()).build();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    c.bench_function("fetch file", |b| {
+        b.iter(|| {
+            runtime
+                .block_on(fetch_feed(&client, &feed, 0, &FetchOptions::default()))
+                .unwrap()
+        })
+    });
+
+    std::fs::remove_file(path).unwrap();
+}
+
+fn merge(c: &mut Criterion) {
+    let batches = batches(&large_feed(100));
+    let options = MergeOptions::default();
+
+    c.bench_function("merge", |b| {
+        b.iter_batched(
+            || batches.clone(),
+            |batches| merge_and_sort(batches, &options),
+            criterion::BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(benches, parse, fetch, merge);
+criterion_main!(benches);
@@ -0,0 +1,2594 @@
+//! The command line: arguments, subcommands, and the state kept between runs.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{IsTerminal, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process::{ExitCode, Stdio},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    prelude::*,
+    TimeDelta,
+};
+use chrono_tz::Tz;
+use clap::{Parser, Subcommand, ValueEnum};
+use directories::ProjectDirs;
+use http_cache_reqwest::{CACacheManager, Cache, CacheMode, HttpCache, HttpCacheOptions};
+use kdl::KdlNode;
+use miette::{bail, miette, Context, IntoDiagnostic, Report, Result};
+use owo_colors::{OwoColorize, Style};
+use regex::RegexBuilder;
+use reqwest::{redirect, Client, Url};
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+use serde::{Deserialize, Serialize};
+use syndication::Feed;
+use tokio::{io::AsyncWriteExt, sync::Semaphore, task::JoinSet, time::Instant};
+
+use crate::{
+    config::{
+        is_group_node, migrate_config, read_opml, CacheModeArg, ConfigFile, FeedSpec, Settings,
+        CONFIG_ENV_VAR,
+    },
+    fetch::{
+        collapse_whitespace, default_user_agent, display_url, document_items, feed_request,
+        fetch_feed, is_insecure_url, read_feed_body, redact_url, same_url, self_link,
+        send_feed_request, update_hint, websub_hubs, FeedBatch, FeedFailure, FetchError, FetchNote,
+        FetchOptions, BROWSER_USER_AGENT,
+    },
+    merge::{
+        explain, merge_and_sort, normalize_link, sort_items, trim_batch, DedupeKey, FeedSort,
+        ItemFilter, MergeOptions, SortOrder,
+    },
+    render::{
+        output_width, parse_field_style, render, render_json, sparkline, FeedStats, Format,
+        GroupBy, HeaderStyle, JsonShape, RenderOptions, StyledField, Template, Theme, ThemeName,
+        SPARKLINE_DAYS,
+    },
+    stable_item_id, write_atomically, FeedItem,
+};
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    /// How many entries to return [default: 20]
+    ///
+    /// Overrides `limit` in the config file's `settings` node.
+    #[arg(short = 'n', long)]
+    limit: Option<usize>,
+
+    /// Skip this many items of the merged list, to show later pages together with --limit
+    #[arg(long, default_value_t = 0)]
+    offset: usize,
+
+    /// How many entries to take from each feed before merging [default: --limit plus --offset]
+    ///
+    /// Overrides `per-feed-limit` in the config file's `settings` node.
+    /// Lowering this below --limit keeps one busy feed from filling the whole list.
+    #[arg(long)]
+    per_feed_limit: Option<usize>,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Lay out the items with this Handlebars-style template instead
+    ///
+    /// Text before `{{#each items}}` is printed once at the top, the part up to `{{/each}}`
+    /// once for each item, and the rest once at the bottom. Fields like `{{title}}` are
+    /// HTML-escaped; `{{{title}}}` leaves them as they are.
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["format", "json_shape", "stats", "metrics", "count_only", "exec", "only_errors"]
+    )]
+    template_file: Option<PathBuf>,
+
+    /// The --template-file, read and checked before anything is fetched.
+    #[arg(skip)]
+    template: Option<Template>,
+
+    /// Resolve each feed's favicon and include it in structured output
+    #[arg(long)]
+    favicons: bool,
+
+    /// Save each feed's response body, exactly as received, to a file in this directory
+    #[arg(long, value_name = "DIR", conflicts_with = "input")]
+    dump_raw: Option<PathBuf>,
+
+    /// When a feed's URL is just a domain without a feed, look for one at the usual paths
+    #[arg(long)]
+    probe: bool,
+
+    /// As a last resort for feeds that don't parse, scrape whatever items can be found out of them
+    #[arg(long)]
+    recover: bool,
+
+    /// For feeds split into pages, follow up to this many `rel="next"` links to find more items
+    ///
+    /// Pages are only followed while a feed has fewer items than its per-feed limit.
+    #[arg(long, value_name = "PAGES", default_value_t = 0)]
+    follow_next: usize,
+
+    /// Take items from each feed in turn instead of ordering strictly by date
+    #[arg(long)]
+    interleave: bool,
+
+    /// Show only the newest item from each feed, however many feeds there are
+    #[arg(
+        long,
+        conflicts_with_all = ["limit", "per_feed_limit", "first", "sort_within_feed", "interleave"]
+    )]
+    latest_per_feed: bool,
+
+    /// Show a short plain-text summary under each item
+    #[arg(long)]
+    show_summary: bool,
+
+    /// Show the configured URL of each item's feed
+    #[arg(long)]
+    show_url: bool,
+
+    /// Show when each item was published before its title
+    #[arg(long)]
+    show_date: bool,
+
+    /// With --show-date, format dates with this strftime pattern
+    #[arg(
+        long,
+        requires = "show_date",
+        value_name = "FORMAT",
+        default_value = "%Y-%m-%d %H:%M",
+        value_parser = parse_date_format
+    )]
+    date_format: String,
+
+    /// Colors to use in text output [default: default]
+    ///
+    /// Overrides `theme` in the config file's `settings` node.
+    #[arg(long, value_enum)]
+    theme: Option<ThemeName>,
+
+    /// Style one part of each item differently from the theme, like `date=dimmed` or `title=bold`
+    ///
+    /// Parts are `date`, `source` (the feed's name), and `title`. Styles are any of `bold`,
+    /// `dimmed`, `italic`, `underline`, a color like `cyan` or `bright-red`, or a hex color like
+    /// `#268bd2`, separated by spaces; `plain` clears the theme's style. Can be repeated.
+    #[arg(long = "style", value_name = "PART=STYLE", value_parser = parse_field_style)]
+    styles: Vec<(StyledField, Style)>,
+
+    /// When to color text output
+    ///
+    /// `auto` colors output written to a terminal, unless the NO_COLOR environment variable is set.
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Show each item's categories as `#tag` suffixes
+    #[arg(long)]
+    show_tags: bool,
+
+    /// Show a short hash of each item's id, which stays the same between runs
+    #[arg(long)]
+    with_id: bool,
+
+    /// Instead of items, show how many items each feed posted in the last day, week, and month
+    #[arg(long)]
+    stats: bool,
+
+    /// Instead of items, print metrics about fetching the feeds in the Prometheus text format
+    #[arg(long, conflicts_with_all = ["stats", "input", "count_only"])]
+    metrics: bool,
+
+    /// Instead of items, report how long fetching, parsing, merging, and rendering the feeds took
+    ///
+    /// Each stage finishes for every feed before the next one starts, so they can be timed apart.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "watch", "input", "stats", "metrics", "only_errors", "count_only", "exec", "mark_read",
+            "archive", "digest_cache",
+        ]
+    )]
+    bench: bool,
+
+    /// Instead of items, list the feeds that failed to load and why, failing if there are any
+    ///
+    /// Text output has one tab-separated line per feed: its URL, a category like `http`, and the
+    /// error message.
+    #[arg(
+        long,
+        conflicts_with_all = ["stats", "metrics", "input", "count_only", "exec", "mark_read"]
+    )]
+    only_errors: bool,
+
+    /// Instead of fetching feeds, read previously output JSON items from this file (`-` for stdin)
+    ///
+    /// Accepts JSON Lines, one item per line, or the array printed by `--format json`.
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["watch", "stats"])]
+    input: Option<PathBuf>,
+
+    /// Write the output to this file instead of stdout
+    ///
+    /// The file is replaced in one step, so readers never see a partly written file.
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Show exactly one line per item, cut short to fit the width instead of wrapping
+    #[arg(long)]
+    compact: bool,
+
+    /// With --compact, put this many items side by side on each line, each cut short to its share
+    /// of the width
+    #[arg(long, value_name = "N", requires = "compact")]
+    items_per_line: Option<NonZeroUsize>,
+
+    /// Show text output through $PAGER (or `less -R`) when writing to a terminal
+    #[arg(long)]
+    pager: bool,
+
+    /// Also fetch this feed, which doesn't need to be in the configuration (can be repeated)
+    #[arg(long = "url", value_name = "URL")]
+    urls: Vec<Url>,
+
+    /// Ignore the configuration file and only fetch the --url feeds
+    #[arg(long)]
+    no_config: bool,
+
+    /// Fetch the feeds listed in this OPML file instead of the configured ones, without importing them
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["input", "no_config"])]
+    opml: Option<PathBuf>,
+
+    /// Only fetch feeds whose name or URL matches this pattern (can be repeated)
+    ///
+    /// Patterns containing `*` (any run of characters) or `?` (any single character)
+    /// must match the whole name or URL; other patterns match anywhere within it.
+    /// Matching ignores case.
+    #[arg(long = "feed", value_name = "PATTERN")]
+    feed_patterns: Vec<String>,
+
+    /// Skip feeds whose host matches this pattern, like `*.example.com` (can be repeated)
+    ///
+    /// Uses the same pattern syntax as `--feed`.
+    #[arg(long = "exclude-host", value_name = "PATTERN")]
+    exclude_hosts: Vec<String>,
+
+    /// Only fetch the feeds in this configuration group, by its node name (can be repeated)
+    #[arg(long = "group", value_name = "NAME")]
+    groups: Vec<String>,
+
+    /// Only show items with this category (can be repeated to allow any of them)
+    ///
+    /// Matching ignores case.
+    #[arg(long = "tag", value_name = "NAME")]
+    tags: Vec<String>,
+
+    /// Hide items with this category (can be repeated)
+    ///
+    /// Matching ignores case, and this wins over --tag.
+    #[arg(long = "exclude-tag", value_name = "NAME")]
+    exclude_tags: Vec<String>,
+
+    /// Record every fetched item not seen before in `archive.jsonl` in the state directory
+    #[arg(long)]
+    archive: bool,
+
+    /// Leave out feeds with fewer than this many items after the other filters
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    min_items: usize,
+
+    /// Also show items marked as read
+    #[arg(long)]
+    include_read: bool,
+
+    /// Mark the items shown as read, so later runs hide them
+    #[arg(long, conflicts_with = "stats")]
+    mark_read: bool,
+
+    /// Hide repeated items, identifying them by this key
+    #[arg(long, value_enum, value_name = "KEY")]
+    dedupe_by: Option<DedupeKey>,
+
+    /// Show items with the same title from several feeds as one line, noting how many feeds had it
+    #[arg(long)]
+    cluster_by_title: bool,
+
+    /// With --dedupe-by, only hide repeats published within this long of each other, like `7d`
+    ///
+    /// Takes a number with a unit: `s`, `m`, `h`, `d`, or `w`.
+    #[arg(long, requires = "dedupe_by", value_name = "DURATION", value_parser = parse_duration)]
+    dedupe_window: Option<TimeDelta>,
+
+    /// Describe on stderr why each item was shown or left out
+    #[arg(long)]
+    explain: bool,
+
+    /// Only show items published at or after this time
+    ///
+    /// Accepts RFC 3339 (`2024-01-01T09:00:00+01:00`), or a date and time without an offset
+    /// (`2024-01-01 09:00`, `2024-01-01`), which is read in the --tz zone.
+    #[arg(long, value_name = "DATETIME")]
+    since: Option<String>,
+
+    /// Only show items published since the last run in which every feed loaded
+    ///
+    /// The first run shows everything, up to the --limit.
+    #[arg(long, conflicts_with = "since")]
+    since_last_run: bool,
+
+    /// Like --since-last-run, but keep the time in this file, as an RFC 3339 timestamp
+    ///
+    /// A missing file means there is no boundary yet.
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["since", "since_last_run"])]
+    since_file: Option<PathBuf>,
+
+    /// Time zone for reading --since values without an offset and for showing dates, like `Europe/Berlin`
+    ///
+    /// Defaults to the system's local time zone.
+    #[arg(long, value_name = "ZONE", value_parser = parse_tz)]
+    tz: Option<Tz>,
+
+    /// Print only the number of items that would be shown
+    #[arg(long)]
+    count_only: bool,
+
+    /// Pipe the items as JSON to this shell command and show what it prints instead
+    ///
+    /// The JSON takes the --json-shape, whatever the --format.
+    #[arg(long, value_name = "CMD", conflicts_with_all = ["stats", "metrics", "count_only"])]
+    exec: Option<String>,
+
+    /// Exit with a failure status if any feed could not be loaded,
+    /// or if --count-only counted no items
+    #[arg(long)]
+    strict: bool,
+
+    /// How to order the merged items
+    #[arg(long, value_enum, default_value_t = SortOrder::Date)]
+    sort: SortOrder,
+
+    /// How to order each feed's items before taking the first --per-feed-limit of them
+    #[arg(long, value_enum, default_value_t = FeedSort::Date)]
+    sort_within_feed: FeedSort,
+
+    /// Order items by the value of this extension element, like `itunes:episode`
+    ///
+    /// Values that are all numbers compare as numbers, and anything else as text.
+    /// Items without the element come last.
+    #[arg(
+        long,
+        value_name = "PREFIX:NAME",
+        value_parser = parse_extension_name,
+        conflicts_with = "interleave"
+    )]
+    sort_by_extension: Option<(String, String)>,
+
+    /// Take the oldest items instead of the newest, listing them oldest first
+    #[arg(long, visible_alias = "oldest")]
+    first: bool,
+
+    /// Print items in sections with a header for each group
+    #[arg(long, value_enum)]
+    group_by: Option<GroupBy>,
+
+    /// Show at most this many items from each group of feeds in the configuration, before applying --limit
+    ///
+    /// Feeds outside any group aren't capped.
+    #[arg(long, value_name = "N")]
+    limit_per_group: Option<usize>,
+
+    /// Put this many blank lines between --group-by sections
+    #[arg(long, value_name = "N", default_value_t = 1, requires = "group_by")]
+    group_separator: usize,
+
+    /// How to draw the header of each --group-by section
+    #[arg(
+        long,
+        value_enum,
+        value_name = "STYLE",
+        default_value_t = HeaderStyle::Theme,
+        requires = "group_by"
+    )]
+    group_header_style: HeaderStyle,
+
+    /// Keep running, fetching the feeds again every this many seconds
+    #[arg(long, value_name = "SECONDS")]
+    watch: Option<u64>,
+
+    /// With --watch, exit after this many refreshes instead of running forever
+    #[arg(long, requires = "watch", value_name = "N")]
+    cycles: Option<u32>,
+
+    /// With --watch, hold back each feed's fetch by a random delay of up to this many seconds
+    ///
+    /// Spreads out the requests of each refresh after the first, which is kinder to hosts
+    /// serving several feeds. The delay never exceeds the --watch interval.
+    #[arg(long, requires = "watch", value_name = "SECONDS")]
+    jitter: Option<u64>,
+
+    /// Stop at the first feed that fails to load and exit with an error
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Treat configuration warnings, like unknown feed properties, as errors
+    #[arg(long)]
+    strict_config: bool,
+
+    /// Refuse to fetch any feed over plain HTTP, except from this machine
+    #[arg(long)]
+    require_https: bool,
+
+    /// Pretend it is this RFC 3339 time, for reproducible output in tests and demos
+    #[arg(long, value_name = "DATETIME", value_parser = parse_rfc3339)]
+    now: Option<DateTime<FixedOffset>>,
+
+    /// Send this User-Agent header instead of the default
+    #[arg(long)]
+    user_agent: Option<String>,
+
+    /// Send the User-Agent header of a common web browser, for servers that block anything else
+    #[arg(long, conflicts_with = "user_agent")]
+    browser_ua: bool,
+
+    /// Read the configuration from this file
+    #[arg(long, env = "DASHBOARD_FEEDS_CONFIG_FILE")]
+    config: Option<PathBuf>,
+
+    /// Store the HTTP cache in this directory
+    #[arg(long, env = "DASHBOARD_FEEDS_CACHE_DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// Store persistent state in this directory
+    #[arg(long, env = "DASHBOARD_FEEDS_STATE_DIR")]
+    state_dir: Option<PathBuf>,
+
+    /// Fetch at most this many feeds at once [default: all of them]
+    ///
+    /// Overrides `jobs` in the config file's `settings` node.
+    #[arg(short, long, value_name = "N")]
+    jobs: Option<NonZeroUsize>,
+
+    /// Keep at most this many idle connections open to each host [default: no limit]
+    #[arg(long, value_name = "N", help_heading = "Advanced")]
+    pool_max_idle_per_host: Option<usize>,
+
+    /// Close idle connections after this many seconds [default: 90]
+    #[arg(long, value_name = "SECONDS", help_heading = "Advanced")]
+    pool_idle_timeout: Option<u64>,
+
+    /// Follow at most this many redirects for each feed, or none with 0 [default: 9]
+    #[arg(long, value_name = "N", help_heading = "Advanced")]
+    max_redirects: Option<usize>,
+
+    /// Refuse to fetch feeds from servers that only offer TLS older than this
+    #[arg(long, value_enum, default_value_t = TlsVersion::Tls12, help_heading = "Advanced")]
+    min_tls: TlsVersion,
+
+    /// Reuse the output of an identical run from less than this many seconds ago instead of fetching
+    ///
+    /// Handy when several widgets poll the same command.
+    #[arg(long, value_name = "SECONDS", conflicts_with = "watch")]
+    digest_cache: Option<u64>,
+
+    /// Stop waiting for feeds after this many seconds and show whatever has arrived
+    #[arg(long, value_name = "SECONDS")]
+    deadline: Option<u64>,
+
+    /// How to use the HTTP cache
+    #[arg(long, value_enum, default_value_t = CacheModeArg::Default)]
+    cache_mode: CacheModeArg,
+
+    /// Shape of the JSON output
+    #[arg(long, value_enum, default_value_t = JsonShape::Array)]
+    json_shape: JsonShape,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check the configuration, cache directory, and connectivity to each feed
+    Doctor,
+    /// Mark items as read, so they are hidden from later runs
+    Read {
+        /// The link, guid, or Atom id of each item
+        #[arg(required = true, value_name = "LINK_OR_ID")]
+        items: Vec<String>,
+    },
+    /// Search the items recorded with --archive, without fetching anything
+    ///
+    /// Matches titles, ignoring case. Options like --since, --limit, and --format still apply.
+    Search {
+        /// Text to look for in item titles
+        query: String,
+
+        /// Treat the query as a regular expression
+        #[arg(long)]
+        regex: bool,
+    },
+    /// Manage the configuration file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Compare each feed's configured URL to the URL the feed says is its own
+    Lint {
+        /// Update the configuration file to use the feeds' own URLs
+        ///
+        /// The original file is backed up next to it with a `.bak` extension.
+        #[arg(long)]
+        fix: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Rewrite the configuration file in the current recommended structure
+    ///
+    /// The original file is backed up next to it with a `.bak` extension.
+    Migrate,
+}
+
+/// TLS versions that --min-tls accepts.
+///
+/// Older versions are left out because rustls never speaks them.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum TlsVersion {
+    #[value(name = "1.2")]
+    Tls12,
+    #[value(name = "1.3")]
+    Tls13,
+}
+
+impl From<TlsVersion> for reqwest::tls::Version {
+    fn from(version: TlsVersion) -> Self {
+        match version {
+            TlsVersion::Tls12 => reqwest::tls::Version::TLS_1_2,
+            TlsVersion::Tls13 => reqwest::tls::Version::TLS_1_3,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ColorChoice {
+    /// Color output written to a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether output going to stdout, or to --output, should be colored.
+    fn enabled(self, args: &Args) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                args.output.is_none()
+                    && std::io::stdout().is_terminal()
+                    && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            }
+        }
+    }
+}
+
+impl Theme {
+    /// The theme chosen by --theme with any --style changes, or no styling at all
+    /// if colors are turned off.
+    fn for_args(args: &Args) -> Self {
+        if args.color.enabled(args) {
+            let mut theme = Theme::new(args.theme.unwrap_or(ThemeName::Default));
+            for &(field, style) in &args.styles {
+                match field {
+                    StyledField::Date => theme.date = style,
+                    StyledField::Source => theme.source = style,
+                    StyledField::Title => theme.title = style,
+                }
+            }
+            theme
+        } else {
+            Theme::plain()
+        }
+    }
+}
+
+impl RenderOptions {
+    fn for_args(args: &Args) -> Self {
+        Self {
+            format: args.format,
+            template: args.template.clone(),
+            json_shape: args.json_shape,
+            theme: Theme::for_args(args),
+            color: args.color.enabled(args),
+            width: output_width(),
+            show_date: args.show_date,
+            date_format: args.date_format.clone(),
+            tz: args.tz,
+            show_url: args.show_url,
+            show_tags: args.show_tags,
+            show_summary: args.show_summary,
+            with_id: args.with_id,
+            compact: args.compact,
+            items_per_line: args.items_per_line,
+            group_by: args.group_by,
+            group_separator: args.group_separator,
+            group_header_style: args.group_header_style,
+            oldest_first: args.first,
+            now: current_time(args),
+        }
+    }
+}
+
+impl MergeOptions {
+    fn for_args(args: &Args) -> Self {
+        Self {
+            oldest_first: args.first,
+            sort: args.sort,
+            by_extension: args.sort_by_extension.is_some(),
+            interleave: args.interleave,
+            latest_per_feed: args.latest_per_feed,
+            dedupe_by: args.dedupe_by,
+            dedupe_window: args.dedupe_window,
+            cluster_by_title: args.cluster_by_title,
+            limit_per_group: args.limit_per_group,
+            explain: args.explain,
+        }
+    }
+}
+
+impl ItemFilter {
+    fn new(args: &Args, since: Option<DateTime<FixedOffset>>, read: Option<ReadState>) -> Self {
+        let lowercase = |tags: &[String]| tags.iter().map(|tag| tag.to_lowercase()).collect();
+
+        ItemFilter {
+            since,
+            include_tags: lowercase(&args.tags),
+            exclude_tags: lowercase(&args.exclude_tags),
+            read: read.map(Arc::new),
+            min_items: args.min_items,
+            explain: args.explain,
+        }
+    }
+}
+
+/// The items marked as read, kept in the state directory.
+#[derive(Default)]
+pub(crate) struct ReadState {
+    keys: HashSet<String>,
+}
+
+impl ReadState {
+    fn path(dirs: &Dirs) -> PathBuf {
+        dirs.state_dir.join("read.json")
+    }
+
+    /// Loads the read items, of which there are none before anything is marked.
+    fn load(dirs: &Dirs) -> Result<Self> {
+        let path = Self::path(dirs);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                return Err(e)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Could not read {}", path.display()))
+            }
+        };
+
+        let keys = serde_json::from_str(&content)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Could not parse {}", path.display()))?;
+        Ok(Self { keys })
+    }
+
+    fn save(&self, dirs: &Dirs) -> Result<()> {
+        let path = Self::path(dirs);
+        let mut keys: Vec<&String> = self.keys.iter().collect();
+        keys.sort();
+
+        fs::create_dir_all(&dirs.state_dir).into_diagnostic()?;
+        write_atomically(
+            &path,
+            serde_json::to_string_pretty(&keys)
+                .into_diagnostic()?
+                .as_bytes(),
+        )
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Could not write {}", path.display()))
+    }
+
+    /// The ways an item can be recognized again: its guid or id, and its link.
+    fn item_keys(item: &FeedItem) -> impl Iterator<Item = String> + '_ {
+        [
+            item.guid().map(String::from),
+            Some(normalize_link(&item.link)),
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|key| !key.is_empty())
+    }
+
+    pub(crate) fn contains(&self, item: &FeedItem) -> bool {
+        Self::item_keys(item).any(|key| self.keys.contains(&key))
+    }
+
+    fn mark(&mut self, item: &FeedItem) {
+        self.keys.extend(Self::item_keys(item));
+    }
+
+    /// Marks whatever item has this guid, id, or link.
+    fn mark_key(&mut self, key: &str) {
+        let key = key.trim();
+        self.keys.insert(key.to_string());
+        self.keys.insert(normalize_link(key));
+    }
+}
+
+/// An item as stored in the archive, along with the key it is deduplicated by.
+#[derive(Serialize)]
+struct ArchivedItem<'a> {
+    key: String,
+    #[serde(flatten)]
+    item: &'a FeedItem,
+}
+
+#[derive(Deserialize)]
+struct ArchivedKey {
+    key: String,
+}
+
+/// Adds items not seen before to the archive of every item, `archive.jsonl` in the state directory.
+///
+/// The archive is one JSON item per line, so it can be read back with `--input`.
+fn archive_items(dirs: &Dirs, items: &[FeedItem]) -> Result<()> {
+    let path = archive_path(dirs);
+    let mut content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(e)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Could not read {}", path.display()))
+        }
+    };
+
+    let mut seen: HashSet<String> = content
+        .lines()
+        .filter_map(|line| serde_json::from_str::<ArchivedKey>(line).ok())
+        .map(|archived| archived.key)
+        .collect();
+
+    let mut added = false;
+    for item in items {
+        let Some(key) = item.dedupe_key(DedupeKey::Guid) else {
+            continue;
+        };
+        if !seen.insert(key.clone()) {
+            continue;
+        }
+
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push_str(&serde_json::to_string(&ArchivedItem { key, item }).into_diagnostic()?);
+        content.push('\n');
+        added = true;
+    }
+
+    if added {
+        // Rewriting the whole file keeps it intact even if we are interrupted part way through.
+        fs::create_dir_all(&dirs.state_dir).into_diagnostic()?;
+        write_atomically(&path, content.as_bytes())
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Could not write {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+fn archive_path(dirs: &Dirs) -> PathBuf {
+    dirs.state_dir.join("archive.jsonl")
+}
+
+/// Where the time the last successful run started is kept, with --since-last-run in the
+/// state directory and with --since-file wherever the caller likes.
+fn last_run_path(args: &Args, dirs: &Dirs) -> Option<PathBuf> {
+    if args.since_last_run {
+        Some(dirs.state_dir.join("last-run"))
+    } else {
+        args.since_file.clone()
+    }
+}
+
+/// The time the last successful run started, or `None` before the first one.
+fn load_last_run(path: &Path) -> Result<Option<DateTime<FixedOffset>>> {
+    match fs::read_to_string(path) {
+        Ok(content) => DateTime::parse_from_rfc3339(content.trim())
+            .map(Some)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Could not parse {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Could not read {}", path.display())),
+    }
+}
+
+fn save_last_run(path: &Path, started: DateTime<FixedOffset>) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).into_diagnostic()?;
+    }
+    write_atomically(path, format!("{}\n", started.to_rfc3339()).as_bytes())
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Could not write {}", path.display()))
+}
+
+/// Prints the archived items whose titles match `query`, newest first.
+fn search(args: &Args, dirs: &Dirs, query: &str, regex: bool) -> Result<()> {
+    let pattern = if regex {
+        query.to_string()
+    } else {
+        regex::escape(query)
+    };
+    let pattern = RegexBuilder::new(&pattern)
+        .case_insensitive(true)
+        .build()
+        .into_diagnostic()
+        .wrap_err("Invalid search pattern")?;
+
+    let path = archive_path(dirs);
+    if !path.exists() {
+        bail!(
+            help = "Run with --archive to start recording items",
+            "No archive found at {}",
+            path.display()
+        );
+    }
+
+    let (settings, _) = load_config_for_args(args, dirs)?;
+    let limit = args.limit.or(settings.limit).unwrap_or(20);
+    let since = args
+        .since
+        .as_deref()
+        .map(|since| parse_since(since, args.tz))
+        .transpose()?;
+    let filter = ItemFilter::new(args, since, None);
+
+    let mut items: Vec<FeedItem> = read_input(&path)?
+        .into_iter()
+        .flatten()
+        .filter(|item| pattern.is_match(&item.title) && filter.allows(item))
+        .collect();
+    sort_items(&mut items, &MergeOptions::for_args(args));
+
+    let shown: Vec<&FeedItem> = items.iter().skip(args.offset).take(limit).collect();
+    emit(&render(&shown, &[], &RenderOptions::for_args(args))?, args)
+}
+
+/// Matches text against a pattern as described for `--feed`, ignoring case.
+fn pattern_matches(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let text = text.to_lowercase();
+
+    if pattern.contains(['*', '?']) {
+        glob_matches(
+            &pattern.chars().collect::<Vec<_>>(),
+            &text.chars().collect::<Vec<_>>(),
+        )
+    } else {
+        text.contains(&pattern)
+    }
+}
+
+/// Matches a whole string against a glob pattern supporting `*` and `?`.
+fn glob_matches(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` swallow one more character and try again.
+                Some((star, star_t)) => {
+                    p = star + 1;
+                    t = star_t + 1;
+                    backtrack = Some((star, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Applies `--feed`, `--exclude-host`, and `--group` to the configured feeds.
+fn select_feeds(feeds: Vec<FeedSpec>, args: &Args) -> Vec<FeedSpec> {
+    feeds
+        .into_iter()
+        .filter(|feed| {
+            args.feed_patterns.is_empty()
+                || args.feed_patterns.iter().any(|pattern| {
+                    pattern_matches(pattern, &feed.url)
+                        || feed
+                            .name
+                            .as_ref()
+                            .is_some_and(|name| pattern_matches(pattern, name))
+                })
+        })
+        .filter(|feed| {
+            let host = Url::parse(&feed.url)
+                .ok()
+                .and_then(|url| url.host_str().map(String::from));
+
+            !host.is_some_and(|host| {
+                args.exclude_hosts
+                    .iter()
+                    .any(|pattern| pattern_matches(pattern, &host))
+            })
+        })
+        .filter(|feed| {
+            args.groups.is_empty()
+                || feed
+                    .group
+                    .as_ref()
+                    .is_some_and(|group| args.groups.contains(group))
+        })
+        .collect()
+}
+
+/// Splits an extension element name like `itunes:episode` into its prefix and local name.
+fn parse_extension_name(value: &str) -> std::result::Result<(String, String), String> {
+    match value.split_once(':') {
+        Some((prefix, name)) if !prefix.is_empty() && !name.is_empty() => {
+            Ok((prefix.to_string(), name.to_string()))
+        }
+        _ => Err("expected a namespace prefix and element name, like `itunes:episode`".to_string()),
+    }
+}
+
+/// Parses a duration like `90m` or `7d`.
+fn parse_duration(value: &str) -> std::result::Result<TimeDelta, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or("expected a unit after the number, like `7d`")?;
+    let (amount, unit) = value.split_at(split);
+    let amount: i64 = amount
+        .parse()
+        .map_err(|_| format!("expected a number before `{unit}`"))?;
+
+    let delta = match unit {
+        "s" => TimeDelta::try_seconds(amount),
+        "m" => TimeDelta::try_minutes(amount),
+        "h" => TimeDelta::try_hours(amount),
+        "d" => TimeDelta::try_days(amount),
+        "w" => TimeDelta::try_weeks(amount),
+        _ => return Err(format!("unknown unit `{unit}`, expected s, m, h, d, or w")),
+    };
+    delta.ok_or_else(|| "duration is too long".to_string())
+}
+
+/// Checks that a `--date-format` pattern only uses specifiers chrono understands.
+fn parse_date_format(format: &str) -> std::result::Result<String, String> {
+    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+        return Err("not a valid strftime pattern".to_string());
+    }
+
+    Ok(format.to_string())
+}
+
+/// The time to treat as now, which `--now` can pin for reproducible output.
+fn current_time(args: &Args) -> DateTime<FixedOffset> {
+    args.now.unwrap_or_else(|| Utc::now().fixed_offset())
+}
+
+fn parse_rfc3339(value: &str) -> std::result::Result<DateTime<FixedOffset>, String> {
+    DateTime::parse_from_rfc3339(value.trim()).map_err(|e| e.to_string())
+}
+
+/// Parses an IANA time zone name for `--tz`.
+fn parse_tz(zone: &str) -> std::result::Result<Tz, String> {
+    zone.parse().map_err(|e| format!("{e}"))
+}
+
+/// Parses a `--since` boundary.
+///
+/// Values with an explicit offset are taken as-is.
+/// Anything else is a local date and time, read in `tz` or the system's zone.
+fn parse_since(value: &str, tz: Option<Tz>) -> Result<DateTime<FixedOffset>> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+        return Ok(datetime);
+    }
+
+    let naive = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .map(|date| date.and_time(NaiveTime::MIN))
+        })
+        .ok_or_else(|| {
+            miette!(
+                help = "Use a format like \"2024-01-01T09:00:00Z\", \"2024-01-01 09:00\", or \"2024-01-01\"",
+                "Could not understand --since value \"{value}\""
+            )
+        })?;
+
+    // Around a daylight saving change a local time can be ambiguous; take the earlier one.
+    let datetime = match tz {
+        Some(tz) => tz
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(|d| d.fixed_offset()),
+        None => Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(|d| d.fixed_offset()),
+    };
+
+    datetime.ok_or_else(|| miette!("--since value \"{value}\" does not exist in that time zone"))
+}
+
+/// Where the program keeps its files.
+pub(crate) struct Dirs {
+    pub config_file: PathBuf,
+    pub cache_dir: PathBuf,
+    pub state_dir: PathBuf,
+}
+
+impl Dirs {
+    /// Resolves each path from its command-line override, falling back to the platform's standard locations.
+    fn resolve(args: &Args) -> Result<Self> {
+        Self::resolve_with(
+            args,
+            ProjectDirs::from("dev", "cosmicrose", "dashboard-feeds"),
+        )
+    }
+
+    /// Resolves the paths given the platform's standard locations, if it has any.
+    ///
+    /// Without them, every path has to be given explicitly.
+    fn resolve_with(args: &Args, project_dirs: Option<ProjectDirs>) -> Result<Self> {
+        let Some(project_dirs) = project_dirs else {
+            let (Some(config_file), Some(cache_dir), Some(state_dir)) =
+                (&args.config, &args.cache_dir, &args.state_dir)
+            else {
+                bail!(
+                    help = "Give each location with --config, --cache-dir, and --state-dir, or with the \
+                            DASHBOARD_FEEDS_CONFIG_FILE, DASHBOARD_FEEDS_CACHE_DIR, and \
+                            DASHBOARD_FEEDS_STATE_DIR environment variables",
+                    "Could not find the standard configuration, cache, and state directories on this system"
+                );
+            };
+
+            return Ok(Self {
+                config_file: config_file.clone(),
+                cache_dir: cache_dir.clone(),
+                state_dir: state_dir.clone(),
+            });
+        };
+
+        Ok(Self {
+            config_file: args
+                .config
+                .clone()
+                .unwrap_or_else(|| project_dirs.config_dir().join("config.kdl")),
+            cache_dir: args
+                .cache_dir
+                .clone()
+                .unwrap_or_else(|| project_dirs.cache_dir().to_path_buf()),
+            state_dir: args.state_dir.clone().unwrap_or_else(|| {
+                project_dirs
+                    .state_dir()
+                    .unwrap_or_else(|| project_dirs.data_local_dir())
+                    .to_path_buf()
+            }),
+        })
+    }
+}
+
+/// Shows output through the user's pager, falling back to printing it directly
+/// if the pager can't be started.
+pub(crate) fn page(output: &str) -> Result<()> {
+    let pager = std::env::var("PAGER")
+        .ok()
+        .filter(|pager| !pager.trim().is_empty())
+        .unwrap_or_else(|| "less -R".to_string());
+    let mut words = pager.split_whitespace();
+    let program = words.next().unwrap_or("less");
+
+    let Ok(mut child) = std::process::Command::new(program)
+        .args(words)
+        .stdin(Stdio::piped())
+        .spawn()
+    else {
+        print!("{output}");
+        return Ok(());
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager closing early (e.g. quitting `less`) is not an error.
+        let _ = stdin.write_all(output.as_bytes());
+    }
+    child.wait().into_diagnostic()?;
+
+    Ok(())
+}
+
+/// Builds the HTTP client used for all feed requests.
+fn build_client(args: &Args, dirs: &Dirs) -> Result<ClientWithMiddleware> {
+    let user_agent = match &args.user_agent {
+        Some(user_agent) => user_agent.clone(),
+        None if args.browser_ua => BROWSER_USER_AGENT.to_string(),
+        None => default_user_agent(),
+    };
+
+    // HTTP/2 is negotiated over TLS whenever a server offers it, which lets
+    // feeds on the same host share one connection instead of each doing a handshake.
+    let mut builder = Client::builder()
+        .brotli(true)
+        .gzip(true)
+        .user_agent(user_agent)
+        .use_rustls_tls()
+        .min_tls_version(args.min_tls.into())
+        .timeout(Duration::from_secs(10));
+    if let Some(max_idle) = args.pool_max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(max_idle);
+    }
+    if let Some(secs) = args.pool_idle_timeout {
+        builder = builder.pool_idle_timeout(Duration::from_secs(secs));
+    }
+    if let Some(max) = args.max_redirects {
+        // The limit counts the original request along with the redirects.
+        builder = builder.redirect(redirect::Policy::limited(max + 1));
+    }
+
+    let client = ClientBuilder::new(builder.build().into_diagnostic()?)
+        .with(Cache(HttpCache {
+            mode: args.cache_mode.into(),
+            manager: CACacheManager {
+                path: dirs.cache_dir.join("http"),
+            },
+            options: HttpCacheOptions::default(),
+        }))
+        .build();
+
+    Ok(client)
+}
+
+/// Checks that the environment is ready to run, reporting each problem found.
+async fn doctor(args: &Args, dirs: &Dirs) -> Result<()> {
+    let mut problems = 0;
+
+    if std::env::var_os(CONFIG_ENV_VAR).is_some() {
+        println!("{} {CONFIG_ENV_VAR}", "Config from:".bold());
+    } else {
+        println!("{} {}", "Config file:".bold(), dirs.config_file.display());
+    }
+    let config = ConfigFile::load(&dirs.config_file);
+    if let Ok(config) = &config {
+        for warning in config.warnings() {
+            println!("{:?}", Report::new(warning));
+        }
+    }
+    let feeds = match config.and_then(|config| config.feeds()) {
+        Ok(feeds) => {
+            println!("  {} {} feeds configured", "ok".green(), feeds.len());
+            feeds
+        }
+        Err(report) => {
+            problems += 1;
+            println!("{report:?}");
+            vec![]
+        }
+    };
+
+    println!("{} {}", "Cache directory:".bold(), dirs.cache_dir.display());
+    match check_writable(&dirs.cache_dir) {
+        Ok(()) => println!("  {} writable", "ok".green()),
+        Err(cause) => {
+            problems += 1;
+            let report = miette!(
+                help = "Fix the directory's permissions, or choose another with --cache-dir",
+                "Cache directory is not writable: {cause}"
+            );
+            println!("{report:?}");
+        }
+    }
+
+    if !feeds.is_empty() {
+        println!("{}", "Connectivity:".bold());
+
+        let client = build_client(args, dirs)?;
+        let mut probes = JoinSet::new();
+        for feed in feeds {
+            let client = client.clone();
+            probes.spawn(async move {
+                let result = async {
+                    let res = send_feed_request(&client, &feed).await?;
+                    let status = res.status();
+                    let body = read_feed_body(res).await?;
+                    let (hubs, hint) = match body.parse::<Feed>() {
+                        Ok(document) => {
+                            let hint = match &document {
+                                Feed::RSS(channel) => update_hint(channel),
+                                Feed::Atom(_) => None,
+                            };
+                            (websub_hubs(&document), hint)
+                        }
+                        Err(_) => (vec![], None),
+                    };
+                    Ok::<_, FetchError>((status, hubs, hint))
+                }
+                .await;
+                (feed.url, result)
+            });
+        }
+
+        for (url, result) in probes.join_all().await {
+            match result {
+                Ok((status, hubs, hint)) => {
+                    println!("  {} {} ({status})", "ok".green(), display_url(&url));
+                    for hub in hubs {
+                        println!("     WebSub hub: {hub}");
+                    }
+                    if let Some(hint) = hint {
+                        println!("     Updates at most every {}", format_duration(hint));
+                    }
+                }
+                Err(error) => {
+                    problems += 1;
+                    let failure = FeedFailure::from_error(&url, &error);
+                    println!("  {} {}: {}", "error".red(), failure.url, failure.message);
+                }
+            }
+        }
+    }
+
+    if problems > 0 {
+        bail!("Found {problems} problem(s)");
+    }
+
+    println!("Everything looks good!");
+    Ok(())
+}
+
+/// Reports feeds whose `rel="self"` link differs from the configured URL, optionally fixing them.
+async fn lint(args: &Args, dirs: &Dirs, fix: bool) -> Result<()> {
+    let config = ConfigFile::read(&dirs.config_file)?;
+    let feeds = config.feeds()?;
+
+    let client = build_client(args, dirs)?;
+    let mut probes = JoinSet::new();
+    for feed in feeds {
+        let client = client.clone();
+        probes.spawn(async move {
+            let result = async {
+                let res = send_feed_request(&client, &feed).await?;
+                let body = read_feed_body(res).await?;
+                Ok::<_, FetchError>(self_link(&body))
+            }
+            .await;
+            (feed.url, result)
+        });
+    }
+
+    let mut updates: Vec<(String, String)> = vec![];
+    for (url, result) in probes.join_all().await {
+        match result {
+            Ok(Some(canonical)) if !same_url(&url, &canonical) => {
+                println!("{} {}", "update".yellow(), display_url(&url));
+                println!("  declares its own URL as {}", display_url(&canonical));
+                updates.push((url, canonical));
+            }
+            Ok(_) => println!("  {} {}", "ok".green(), display_url(&url)),
+            Err(error) => {
+                let failure = FeedFailure::from_error(&url, &error);
+                println!("{} {}: {}", "error".red(), failure.url, failure.message);
+            }
+        }
+    }
+
+    if updates.is_empty() {
+        println!("No feeds need updating.");
+        return Ok(());
+    }
+    if !fix {
+        println!("Run with --fix to update the configuration file.");
+        return Ok(());
+    }
+
+    let config_path = &dirs.config_file;
+    let mut config_doc = config.doc;
+    if let Some(feed_nodes) = config_doc
+        .get_mut("feeds")
+        .and_then(|feeds| feeds.children_mut().as_mut())
+    {
+        let update_url = |node: &mut KdlNode| {
+            let Some(entry) = node.entry_mut(0) else {
+                return;
+            };
+            let Some(url) = entry.value().as_string() else {
+                return;
+            };
+            if let Some((_, canonical)) = updates.iter().find(|(old, _)| old == url) {
+                entry.set_value(canonical.clone());
+                // Drop the original spelling so the new value gets written out.
+                entry.clear_format();
+            }
+        };
+
+        for node in feed_nodes.nodes_mut() {
+            if !is_group_node(node) {
+                update_url(node);
+            } else if let Some(children) = node.children_mut() {
+                children.nodes_mut().iter_mut().for_each(update_url);
+            }
+        }
+    }
+
+    let backup_path = config_path.with_extension("kdl.bak");
+    fs::copy(config_path, &backup_path).into_diagnostic()?;
+    write_atomically(config_path, config_doc.to_string().as_bytes()).into_diagnostic()?;
+
+    println!(
+        "Updated {} feed(s) in {} (original saved to {})",
+        updates.len(),
+        config_path.display(),
+        backup_path.display()
+    );
+    Ok(())
+}
+
+/// Checks that files can be created in a directory, creating it if needed.
+fn check_writable(dir: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+
+    let probe = dir.join(".doctor-probe");
+    fs::write(&probe, b"")?;
+    fs::remove_file(probe)
+}
+
+/// Pipes the items as JSON through the --exec command, whose output takes their place.
+async fn run_exec(command: &str, input: String) -> Result<String> {
+    let mut child = shell_command(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Could not run `{command}`"))?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let writer = tokio::spawn(async move { stdin.write_all(input.as_bytes()).await });
+    let output = child
+        .wait_with_output()
+        .await
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Could not run `{command}`"))?;
+    // Commands that stop reading early, like `head`, are fine.
+    let _ = writer.await;
+
+    if !output.status.success() {
+        bail!("`{command}` failed with {}", output.status);
+    }
+    String::from_utf8(output.stdout)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("`{command}` printed output that isn't UTF-8"))
+}
+
+/// Runs a command line through the system shell, so it can use quoting and pipes.
+fn shell_command(command: &str) -> tokio::process::Command {
+    let mut shell = if cfg!(windows) {
+        let mut shell = tokio::process::Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = tokio::process::Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command);
+    shell
+}
+
+/// Formats the feeds that failed to load, for --only-errors.
+fn render_failures(failures: &[FeedFailure], args: &Args) -> Result<String> {
+    let output = match args.format {
+        Format::Text => failures
+            .iter()
+            .map(|failure| {
+                format!(
+                    "{}\t{}\t{}\n",
+                    failure.url,
+                    failure.category,
+                    collapse_whitespace(&failure.message)
+                )
+            })
+            .collect(),
+        Format::Json => serde_json::to_string_pretty(failures).into_diagnostic()? + "\n",
+    };
+
+    Ok(output)
+}
+
+fn render_stats(stats: &[FeedStats], args: &Args) -> Result<String> {
+    let theme = Theme::for_args(args);
+    let output = match args.format {
+        Format::Text => {
+            let mut output = format!(
+                "{}\n",
+                format!(
+                    "{:>4} {:>4} {:>4}  {:<SPARKLINE_DAYS$}  Feed",
+                    "24h", "7d", "30d", ""
+                )
+                .style(theme.header)
+            );
+            for stats in stats {
+                output.push_str(&format!(
+                    "{:>4} {:>4} {:>4}  {}  {}\n",
+                    stats.last_day,
+                    stats.last_week,
+                    stats.last_month,
+                    sparkline(&stats.daily).style(theme.highlight),
+                    stats.feed_title
+                ));
+            }
+            output
+        }
+        Format::Json => serde_json::to_string_pretty(stats).into_diagnostic()? + "\n",
+    };
+
+    Ok(output)
+}
+
+/// Sends finished output to --output, the pager, or stdout.
+fn emit(output: &str, args: &Args) -> Result<()> {
+    if let Some(path) = &args.output {
+        write_atomically(path, output.as_bytes())
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Could not write output to {}", path.display()))?;
+    } else if args.pager && args.format == Format::Text && std::io::stdout().is_terminal() {
+        page(output)?;
+    } else {
+        print!("{output}");
+    }
+
+    Ok(())
+}
+
+/// Runs the program as the command line asked, returning its exit status.
+pub async fn run(mut args: Args) -> Result<ExitCode> {
+    let dirs = Dirs::resolve(&args)?;
+
+    match args.command {
+        Some(Command::Config {
+            command: ConfigCommand::Migrate,
+        }) => return migrate_config(&dirs.config_file).map(|()| ExitCode::SUCCESS),
+        Some(Command::Doctor) => return doctor(&args, &dirs).await.map(|()| ExitCode::SUCCESS),
+        Some(Command::Lint { fix }) => {
+            return lint(&args, &dirs, fix).await.map(|()| ExitCode::SUCCESS)
+        }
+        Some(Command::Search { ref query, regex }) => {
+            return search(&args, &dirs, query, regex).map(|()| ExitCode::SUCCESS)
+        }
+        Some(Command::Read { ref items }) => {
+            let mut read = ReadState::load(&dirs)?;
+            for item in items {
+                read.mark_key(item);
+            }
+            read.save(&dirs)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        None => {}
+    }
+
+    if let Some(path) = &args.template_file {
+        args.template = Some(Template::load(path)?);
+    }
+
+    let (settings, feeds) = load_config_for_args(&args, &dirs)?;
+    args.theme = args.theme.or(settings.theme);
+    args.jobs = args.jobs.or(settings.jobs);
+
+    if args.require_https {
+        let insecure: Vec<String> = feeds
+            .iter()
+            .filter(|feed| feed.socket.is_none() && is_insecure_url(&feed.url))
+            .map(|feed| display_url(&feed.url))
+            .collect();
+        if !insecure.is_empty() {
+            bail!(
+                help = "Switch these feeds to https:// URLs, or leave out --require-https",
+                "Refusing to fetch feeds over plain HTTP: {}",
+                insecure.join(", ")
+            );
+        }
+    }
+
+    let limit = args.limit.or(settings.limit).unwrap_or(20);
+    let per_feed_limit = args
+        .per_feed_limit
+        .or(settings.per_feed_limit)
+        .unwrap_or(limit.saturating_add(args.offset));
+
+    let client = build_client(&args, &dirs)?;
+
+    let since = if let Some(path) = last_run_path(&args, &dirs) {
+        load_last_run(&path)?
+    } else {
+        args.since
+            .as_deref()
+            .map(|since| parse_since(since, args.tz))
+            .transpose()?
+    };
+
+    let limits = Limits {
+        // Every feed's newest item is shown, no matter how many feeds there are.
+        total: if args.latest_per_feed {
+            usize::MAX
+        } else {
+            limit
+        },
+        per_feed: per_feed_limit,
+        since,
+    };
+    let mut held = HeldFeeds::default();
+
+    if args.bench {
+        let status = bench(&args, &dirs, &feeds, &client, limits).await?;
+        return Ok(status.exit_code());
+    }
+
+    let Some(interval) = args.watch else {
+        let status = refresh(&args, &dirs, &feeds, &client, limits, &mut held, None).await?;
+        return Ok(status.exit_code());
+    };
+
+    let mut status = RunStatus::Passed;
+    let mut cycle = 0;
+    loop {
+        if args.format == Format::Text && !args.count_only && args.output.is_none() {
+            // Clear the screen so each refresh replaces the last one.
+            print!("\x1b[2J\x1b[H");
+        }
+
+        // The first refresh shows everything as soon as possible; later ones can take their time.
+        let jitter = args
+            .jitter
+            .filter(|_| cycle > 0)
+            .map(|secs| Duration::from_secs(secs.min(interval)))
+            .filter(|jitter| !jitter.is_zero());
+        status =
+            status.max(refresh(&args, &dirs, &feeds, &client, limits, &mut held, jitter).await?);
+        if status == RunStatus::Interrupted {
+            break;
+        }
+
+        cycle += 1;
+        if args.cycles.is_some_and(|cycles| cycle >= cycles) {
+            break;
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(interval)) => {}
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    Ok(status.exit_code())
+}
+
+/// How a run went, from best to worst.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum RunStatus {
+    Passed,
+    /// Something went wrong that --strict cares about.
+    Failed,
+    /// Stopped early by Ctrl-C.
+    Interrupted,
+}
+
+impl RunStatus {
+    fn exit_code(self) -> ExitCode {
+        match self {
+            RunStatus::Passed => ExitCode::SUCCESS,
+            RunStatus::Failed => ExitCode::FAILURE,
+            RunStatus::Interrupted => ExitCode::from(INTERRUPTED_STATUS),
+        }
+    }
+}
+
+/// The usual exit status for being stopped by SIGINT.
+const INTERRUPTED_STATUS: u8 = 130;
+
+/// Exits straight away on the next Ctrl-C, until dropped.
+///
+/// Once anything has listened for Ctrl-C, it no longer ends the process by default,
+/// so without this a Ctrl-C while nothing is listening would be ignored.
+struct ExitOnInterrupt(tokio::task::JoinHandle<()>);
+
+impl ExitOnInterrupt {
+    fn new() -> Self {
+        Self(tokio::spawn(async {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                std::process::exit(INTERRUPTED_STATUS.into());
+            }
+        }))
+    }
+}
+
+impl Drop for ExitOnInterrupt {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// How many items a run shows, and how far back they may go.
+#[derive(Clone, Copy)]
+struct Limits {
+    /// The most items shown in total.
+    total: usize,
+    /// The most items any one feed contributes.
+    per_feed: usize,
+    since: Option<DateTime<FixedOffset>>,
+}
+
+/// Items kept from feeds that said they won't change for a while, so --watch can skip them.
+#[derive(Default)]
+struct HeldFeeds {
+    /// Keyed by the feed's index, with when to fetch it again.
+    batches: HashMap<usize, (Instant, Vec<FeedItem>)>,
+}
+
+/// Formats a duration for people, in the largest whole unit that fits.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs.is_multiple_of(86400) {
+        format!("{}d", secs / 86400)
+    } else if secs.is_multiple_of(3600) {
+        format!("{}h", secs / 3600)
+    } else if secs.is_multiple_of(60) {
+        format!("{}m", secs / 60)
+    } else {
+        format!("{secs}s")
+    }
+}
+
+/// What came back from fetching every feed.
+struct Fetched {
+    /// Each feed's items, with the feed's index in the list of feeds.
+    batches: Vec<(usize, Vec<FeedItem>)>,
+    failures: Vec<FeedFailure>,
+    /// Whether Ctrl-C stopped the fetch before every feed finished.
+    interrupted: bool,
+    metrics: Vec<FeedMetrics>,
+    /// With --archive, every item fetched, before any filtering or trimming.
+    archived: Vec<FeedItem>,
+}
+
+/// How fetching one feed went, for --metrics.
+struct FeedMetrics {
+    feed_index: usize,
+    /// How long the fetch took, or `None` for feeds --watch didn't need to fetch again.
+    duration: Option<Duration>,
+    /// How many items the feed had, before any filtering.
+    items: usize,
+    from_cache: bool,
+    failed: bool,
+}
+
+/// Formats fetch metrics in the Prometheus text exposition format.
+fn render_metrics(feeds: &[FeedSpec], metrics: &[FeedMetrics]) -> String {
+    let label = |metrics: &FeedMetrics| {
+        display_url(&feeds[metrics.feed_index].url)
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    };
+
+    let mut output = String::new();
+    let mut metric =
+        |name: &str, kind: &str, help: &str, samples: Vec<(Option<String>, String)>| {
+            output.push_str(&format!("# HELP dashboard_feeds_{name} {help}\n"));
+            output.push_str(&format!("# TYPE dashboard_feeds_{name} {kind}\n"));
+            for (feed, value) in samples {
+                match feed {
+                    Some(feed) => output.push_str(&format!(
+                        "dashboard_feeds_{name}{{feed=\"{feed}\"}} {value}\n"
+                    )),
+                    None => output.push_str(&format!("dashboard_feeds_{name} {value}\n")),
+                }
+            }
+        };
+
+    metric(
+        "feeds",
+        "gauge",
+        "Feeds this run was asked to load.",
+        vec![(None, feeds.len().to_string())],
+    );
+    metric(
+        "feeds_failed",
+        "gauge",
+        "Feeds that could not be loaded.",
+        vec![(
+            None,
+            metrics.iter().filter(|m| m.failed).count().to_string(),
+        )],
+    );
+    metric(
+        "cache_hits",
+        "gauge",
+        "Feeds served from the HTTP cache without contacting the server.",
+        vec![(
+            None,
+            metrics.iter().filter(|m| m.from_cache).count().to_string(),
+        )],
+    );
+    metric(
+        "items",
+        "gauge",
+        "Items each feed had, before filtering.",
+        metrics
+            .iter()
+            .filter(|m| !m.failed)
+            .map(|m| (Some(label(m)), m.items.to_string()))
+            .collect(),
+    );
+    metric(
+        "fetch_duration_seconds",
+        "gauge",
+        "How long fetching each feed took.",
+        metrics
+            .iter()
+            .filter_map(|m| Some((Some(label(m)), m.duration?.as_secs_f64().to_string())))
+            .collect(),
+    );
+
+    output
+}
+
+/// Fetches and parses every feed at once, returning each feed's items by its index in `feeds`.
+async fn fetch_feeds(
+    args: &Args,
+    feeds: &[FeedSpec],
+    client: &ClientWithMiddleware,
+    per_feed_limit: usize,
+    filter: &ItemFilter,
+    held: &mut HeldFeeds,
+    jitter: Option<Duration>,
+) -> Result<Fetched> {
+    let mut join_set: JoinSet<FetchOutcome> = JoinSet::new();
+    let mut task_feeds = HashMap::new();
+    let jobs = args.jobs.map(|jobs| Arc::new(Semaphore::new(jobs.get())));
+    let mut feed_batches = vec![];
+    let mut metrics = vec![];
+    let mut archived = vec![];
+
+    let options = FetchOptions {
+        resolve_favicons: args.favicons && args.format != Format::Text,
+        dump_dir: args.dump_raw.clone(),
+        probe: args.probe,
+        recover: args.recover,
+        sort_extension: args.sort_by_extension.clone(),
+        follow_next: args.follow_next,
+        wanted_items: 0,
+    };
+    let oldest_first = args.first;
+    let sort = args.sort_within_feed;
+    let archive = args.archive;
+
+    if let Some(dir) = &args.dump_raw {
+        fs::create_dir_all(dir)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Could not create {}", dir.display()))?;
+    }
+
+    for (feed_index, feed) in feeds.iter().cloned().enumerate() {
+        // Stats count everything, even from feeds with a `limit` of their own.
+        let feed_limit = feed.limit.filter(|_| !args.stats).unwrap_or(per_feed_limit);
+
+        if let Some((until, batch)) = held.batches.get(&feed_index) {
+            if Instant::now() < *until {
+                let batch = trim_batch(batch.clone(), filter, sort, oldest_first, feed_limit);
+                metrics.push(FeedMetrics {
+                    feed_index,
+                    duration: None,
+                    items: batch.len(),
+                    from_cache: true,
+                    failed: false,
+                });
+                feed_batches.push((feed_index, batch));
+                continue;
+            }
+        }
+
+        let task_client = client.clone();
+        let filter = filter.clone();
+        let options = FetchOptions {
+            wanted_items: feed_limit,
+            ..options.clone()
+        };
+        let delay = jitter.map_or(Duration::ZERO, |jitter| jitter.mul_f64(fastrand::f64()));
+        let jobs = jobs.clone();
+
+        let handle = join_set.spawn(async move {
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+            // Held until the task ends, so that at most --jobs fetches run at once.
+            let _permit = match jobs {
+                Some(jobs) => Some(jobs.acquire_owned().await.expect("never closed")),
+                None => None,
+            };
+
+            let started = Instant::now();
+            let fetch = async {
+                let batch = fetch_feed(&task_client, &feed, feed_index, &options).await?;
+
+                // Cut down the list of results here while we are in a separate task
+                // so that we have less sort when all the tasks are joined.
+                let fetched = batch.items.len();
+                let untrimmed = if archive { batch.items.clone() } else { vec![] };
+                let batch = FeedBatch {
+                    items: trim_batch(batch.items, &filter, sort, oldest_first, feed_limit),
+                    ..batch
+                };
+                Ok((batch, fetched, untrimmed))
+            };
+            FetchOutcome {
+                started,
+                result: fetch.await,
+            }
+        });
+
+        task_feeds.insert(handle.id(), feed_index);
+    }
+
+    let mut failures = vec![];
+
+    // Tasks that never finished are counted from when fetching began.
+    let started = Instant::now();
+    let mut deadline = args
+        .deadline
+        .map(|secs| Instant::now() + Duration::from_secs(secs));
+    let mut interrupted = false;
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    let mut exit_on_interrupt = None;
+
+    loop {
+        let next = tokio::select! {
+            next = join_set.join_next_with_id() => next,
+            () = sleep_until_deadline(deadline) => {
+                // Out of time; whatever is still running gets reported as cancelled.
+                join_set.abort_all();
+                deadline = None;
+                continue;
+            }
+            _ = &mut ctrl_c, if !interrupted => {
+                // Stop fetching, but still show whatever already arrived.
+                join_set.abort_all();
+                interrupted = true;
+                // Pressing it again gives up on that, too.
+                exit_on_interrupt.get_or_insert_with(ExitOnInterrupt::new);
+                continue;
+            }
+        };
+        let Some(task_result) = next else {
+            break;
+        };
+
+        let (id, duration) = match &task_result {
+            Ok((id, outcome)) => (*id, outcome.started.elapsed()),
+            Err(error) => (error.id(), started.elapsed()),
+        };
+        metrics.push(FeedMetrics {
+            feed_index: task_feeds[&id],
+            duration: Some(duration),
+            items: 0,
+            from_cache: false,
+            failed: true,
+        });
+
+        match task_result {
+            Ok((
+                id,
+                FetchOutcome {
+                    result: Ok((batch, fetched, untrimmed)),
+                    ..
+                },
+            )) => {
+                archived.extend(untrimmed);
+                for note in &batch.notes {
+                    print_fetch_note(note);
+                }
+                let feed_index = task_feeds[&id];
+                if let Some(metrics) = metrics.last_mut() {
+                    metrics.items = fetched;
+                    metrics.from_cache = batch.from_cache;
+                    metrics.failed = false;
+                }
+                match batch.update_hint.filter(|_| args.watch.is_some()) {
+                    Some(hint) => {
+                        let until = Instant::now() + hint;
+                        held.batches
+                            .insert(feed_index, (until, batch.items.clone()));
+                    }
+                    None => {
+                        held.batches.remove(&feed_index);
+                    }
+                }
+                feed_batches.push((feed_index, batch.items));
+            }
+            Ok((
+                id,
+                FetchOutcome {
+                    result: Err(error), ..
+                },
+            )) => {
+                let url = &feeds[task_feeds[&id]].url;
+                if args.fail_fast {
+                    join_set.abort_all();
+                    return Err(Report::new(error))
+                        .wrap_err_with(|| format!("Could not load {}", display_url(url)));
+                }
+                failures.push(FeedFailure::from_error(url, &error));
+            }
+            Err(error) if interrupted && error.is_cancelled() => {
+                let url = &feeds[task_feeds[&error.id()]].url;
+                failures.push(FeedFailure::new(
+                    url,
+                    "interrupted",
+                    "stopped by Ctrl-C".to_string(),
+                ));
+            }
+            Err(error) => {
+                let url = &feeds[task_feeds[&error.id()]].url;
+                failures.push(FeedFailure::from_join_error(url, error));
+            }
+        }
+    }
+
+    Ok(Fetched {
+        batches: feed_batches,
+        failures,
+        interrupted,
+        metrics,
+        archived,
+    })
+}
+
+/// Shows something [`fetch_feed`] had to say about a feed, as a warning if it went wrong.
+fn print_fetch_note(note: &FetchNote) {
+    if note.is_warning() {
+        eprintln!("{} {note}", "warning:".yellow().bold());
+    } else {
+        eprintln!("{} {note}", "note:".bold());
+    }
+}
+
+/// What a fetch task hands back: when it began fetching, and the feed's trimmed batch
+/// along with how many items it had before trimming and, with --archive, those items.
+struct FetchOutcome {
+    started: Instant,
+    result: Result<(FeedBatch, usize, Vec<FeedItem>), FetchError>,
+}
+
+/// Waits until the --deadline, or forever if there isn't one.
+async fn sleep_until_deadline(deadline: Option<Instant>) {
+    match deadline {
+        Some(at) => tokio::time::sleep_until(at).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Reads previously emitted items, either as JSON Lines or as one JSON array,
+/// grouped into batches by feed in order of first appearance.
+fn read_input(path: &Path) -> Result<Vec<Vec<FeedItem>>> {
+    let content = if path == Path::new("-") {
+        std::io::read_to_string(std::io::stdin()).into_diagnostic()
+    } else {
+        fs::read_to_string(path).into_diagnostic()
+    }
+    .wrap_err_with(|| format!("Could not read items from {}", path.display()))?;
+
+    let items: Vec<FeedItem> = if content.trim_start().starts_with('[') {
+        serde_json::from_str(&content).into_diagnostic()?
+    } else {
+        content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(number, line)| {
+                serde_json::from_str(line)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Invalid item on line {}", number + 1))
+            })
+            .collect::<Result<_>>()?
+    };
+
+    let mut batches: Vec<Vec<FeedItem>> = vec![];
+    let mut feed_indexes: HashMap<String, usize> = HashMap::new();
+    for mut item in items {
+        let next_index = feed_indexes.len();
+        let feed_index = *feed_indexes
+            .entry(item.feed_url.clone())
+            .or_insert(next_index);
+        if feed_index == batches.len() {
+            batches.push(vec![]);
+        }
+        item.feed_index = feed_index;
+        if item.id.is_empty() {
+            // Written before items carried an id.
+            item.id = stable_item_id(None, &item.link, &item.title);
+        }
+        batches[feed_index].push(item);
+    }
+
+    Ok(batches)
+}
+
+/// Loads the settings and the feeds selected to fetch, reporting any configuration warnings.
+fn load_config_for_args(args: &Args, dirs: &Dirs) -> Result<(Settings, Vec<FeedSpec>)> {
+    // Re-rendering and searching saved items don't fetch any feeds.
+    let offline = args.input.is_some() || matches!(args.command, Some(Command::Search { .. }));
+
+    let url_feeds: Vec<FeedSpec> = args.urls.iter().map(FeedSpec::new).collect();
+
+    if let Some(path) = &args.opml {
+        let mut feeds = select_feeds(read_opml(path)?, args);
+        feeds.extend(url_feeds);
+        return Ok((Settings::default(), feeds));
+    }
+
+    if args.no_config {
+        if url_feeds.is_empty() && !offline {
+            bail!(
+                help = "Pass feeds with --url, or saved items with --input",
+                "Nothing to show because --no-config was given without any --url"
+            );
+        }
+        return Ok((Settings::default(), url_feeds));
+    }
+
+    let config_missing = std::env::var_os(CONFIG_ENV_VAR).is_none() && !dirs.config_file.exists();
+    if offline && config_missing {
+        // These work without any configuration.
+        return Ok((Settings::default(), vec![]));
+    }
+    if config_missing && !url_feeds.is_empty() {
+        return Ok((Settings::default(), url_feeds));
+    }
+
+    let config = ConfigFile::load(&dirs.config_file)?;
+    for warning in config.warnings() {
+        if args.strict_config {
+            return Err(warning.into());
+        }
+        eprintln!("{:?}", Report::new(warning));
+    }
+
+    let feeds = if offline {
+        vec![]
+    } else {
+        let configured = config.feeds()?;
+        for group in &args.groups {
+            if !configured
+                .iter()
+                .any(|feed| feed.group.as_ref() == Some(group))
+            {
+                eprintln!(
+                    "{} no feeds are in a group named {group}",
+                    "warning:".yellow().bold()
+                );
+            }
+        }
+
+        let mut feeds = select_feeds(configured, args);
+        feeds.extend(url_feeds);
+        feeds
+    };
+
+    Ok((config.settings()?, feeds))
+}
+
+/// Where the output of a run with these arguments and feeds is kept for --digest-cache.
+fn digest_cache_path(args: &Args, dirs: &Dirs, feeds: &[FeedSpec]) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    format!("{args:?}").hash(&mut hasher);
+    format!("{feeds:?}").hash(&mut hasher);
+
+    dirs.cache_dir
+        .join("digests")
+        .join(format!("{:016x}", hasher.finish()))
+}
+
+/// Returns the output saved at `path` if it was written less than `ttl` ago.
+fn cached_digest(path: &Path, ttl: Duration) -> Option<String> {
+    let age = fs::metadata(path).ok()?.modified().ok()?.elapsed().ok()?;
+    if age >= ttl {
+        return None;
+    }
+
+    fs::read_to_string(path).ok()
+}
+
+fn save_digest(path: &Path, output: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).into_diagnostic()?;
+    }
+    write_atomically(path, output.as_bytes())
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Could not write {}", path.display()))
+}
+
+/// Goes through a run's stages one at a time and prints how long each took, for --bench.
+///
+/// Only the fetch stage works on several feeds at once. Feeds that fail to load are left out
+/// of the later stages, and the rendered items aren't shown.
+async fn bench(
+    args: &Args,
+    dirs: &Dirs,
+    feeds: &[FeedSpec],
+    client: &ClientWithMiddleware,
+    limits: Limits,
+) -> Result<RunStatus> {
+    let mut failures = vec![];
+
+    let started = Instant::now();
+    let jobs = args.jobs.map(|jobs| Arc::new(Semaphore::new(jobs.get())));
+    let mut fetches = JoinSet::new();
+    for (feed_index, feed) in feeds.iter().cloned().enumerate() {
+        let client = client.clone();
+        let jobs = jobs.clone();
+        fetches.spawn(async move {
+            let _permit = match jobs {
+                Some(jobs) => Some(jobs.acquire_owned().await.expect("never closed")),
+                None => None,
+            };
+            let body = async {
+                let res = send_feed_request(&client, &feed).await?;
+                read_feed_body(res).await
+            };
+            (feed_index, body.await)
+        });
+    }
+    let mut bodies = vec![];
+    for (feed_index, body) in fetches.join_all().await {
+        match body {
+            Ok(body) => bodies.push((feed_index, body)),
+            Err(error) => failures.push(FeedFailure::from_error(&feeds[feed_index].url, &error)),
+        }
+    }
+    bodies.sort_by_key(|(feed_index, _)| *feed_index);
+    let fetch_time = started.elapsed();
+
+    let options = FetchOptions {
+        sort_extension: args.sort_by_extension.clone(),
+        ..FetchOptions::default()
+    };
+    let started = Instant::now();
+    let mut batches = vec![];
+    for (feed_index, body) in &bodies {
+        let feed = &feeds[*feed_index];
+        match body.parse::<Feed>() {
+            Ok(document) => batches.push((
+                *feed_index,
+                document_items(&document, feed, *feed_index, None, &options),
+            )),
+            Err(error) => failures.push(FeedFailure::from_error(
+                &feed.url,
+                &FetchError::Parse(error),
+            )),
+        }
+    }
+    let parse_time = started.elapsed();
+    let parsed: usize = batches.iter().map(|(_, batch)| batch.len()).sum();
+
+    let read = if args.include_read {
+        None
+    } else {
+        Some(ReadState::load(dirs)?)
+    };
+    let filter = ItemFilter::new(args, limits.since, read);
+    let started = Instant::now();
+    let batches = batches
+        .into_iter()
+        .map(|(feed_index, batch)| {
+            let feed_limit = feeds[feed_index].limit.unwrap_or(limits.per_feed);
+            trim_batch(
+                batch,
+                &filter,
+                args.sort_within_feed,
+                args.first,
+                feed_limit,
+            )
+        })
+        .collect();
+    let feed_items = merge_and_sort(batches, &MergeOptions::for_args(args));
+    let shown: Vec<&FeedItem> = feed_items
+        .iter()
+        .skip(args.offset)
+        .take(limits.total)
+        .collect();
+    let merge_time = started.elapsed();
+
+    let started = Instant::now();
+    let rendered = render(&shown, &failures, &RenderOptions::for_args(args))?;
+    let render_time = started.elapsed();
+
+    for failure in &failures {
+        eprintln!(
+            "{} could not load {}: {}",
+            "warning:".yellow().bold(),
+            failure.url,
+            failure.message
+        );
+    }
+
+    let stage = |name: &str, time: Duration, detail: String| {
+        format!(
+            "{name:<8}{:>12}  {detail}\n",
+            format!("{:.1} ms", time.as_secs_f64() * 1000.0)
+        )
+    };
+    let report = [
+        stage(
+            "fetch",
+            fetch_time,
+            format!("{} of {} feeds", bodies.len(), feeds.len()),
+        ),
+        stage("parse", parse_time, format!("{parsed} items")),
+        stage("merge", merge_time, format!("{} items kept", shown.len())),
+        stage("render", render_time, format!("{} bytes", rendered.len())),
+    ]
+    .concat();
+    emit(&report, args)?;
+
+    if args.strict && !failures.is_empty() {
+        Ok(RunStatus::Failed)
+    } else {
+        Ok(RunStatus::Passed)
+    }
+}
+
+/// Fetches every feed once, or reads the --input items, and prints what came back.
+///
+/// Returns [`RunStatus::Failed`] if `--strict` should make the program fail because of this run.
+async fn refresh(
+    args: &Args,
+    dirs: &Dirs,
+    feeds: &[FeedSpec],
+    client: &ClientWithMiddleware,
+    limits: Limits,
+    held: &mut HeldFeeds,
+    jitter: Option<Duration>,
+) -> Result<RunStatus> {
+    let Limits {
+        total: limit,
+        per_feed: per_feed_limit,
+        since,
+    } = limits;
+    // Items published while the feeds are loading count as new next time.
+    let started = current_time(args);
+
+    let digest_path = args
+        .digest_cache
+        .map(|ttl| (ttl, digest_cache_path(args, dirs, feeds)));
+    if let Some((ttl, path)) = &digest_path {
+        if let Some(output) = cached_digest(path, Duration::from_secs(*ttl)) {
+            emit(&output, args)?;
+            return Ok(RunStatus::Passed);
+        }
+    }
+
+    // Stats count everything each feed has, not just what gets shown.
+    let per_feed_limit = if args.stats {
+        usize::MAX
+    } else {
+        per_feed_limit
+    };
+
+    let read = if args.include_read {
+        None
+    } else {
+        Some(ReadState::load(dirs)?)
+    };
+    let filter = ItemFilter::new(args, since, read);
+
+    let Fetched {
+        batches: feed_batches,
+        failures,
+        interrupted,
+        metrics,
+        archived,
+    } = match &args.input {
+        Some(path) => {
+            let batches = read_input(path)?;
+            Fetched {
+                archived: if args.archive {
+                    batches.concat()
+                } else {
+                    vec![]
+                },
+                batches: batches
+                    .into_iter()
+                    .map(|batch| {
+                        trim_batch(
+                            batch,
+                            &filter,
+                            args.sort_within_feed,
+                            args.first,
+                            per_feed_limit,
+                        )
+                    })
+                    .enumerate()
+                    .collect(),
+                failures: vec![],
+                interrupted: false,
+                metrics: vec![],
+            }
+        }
+        None => fetch_feeds(args, feeds, client, per_feed_limit, &filter, held, jitter).await?,
+    };
+
+    if args.archive {
+        archive_items(dirs, &archived)?;
+    }
+
+    // Fetching is over, so Ctrl-C goes back to ending the run, even in the middle of --exec.
+    let _exit_on_interrupt = ExitOnInterrupt::new();
+
+    if interrupted {
+        if feed_batches.is_empty() {
+            eprintln!("Interrupted before any feed finished loading.");
+            return Ok(RunStatus::Interrupted);
+        }
+        eprintln!(
+            "{} interrupted, showing the {} of {} feeds that finished",
+            "warning:".yellow().bold(),
+            feed_batches.len(),
+            feeds.len()
+        );
+    }
+
+    for failure in failures
+        .iter()
+        .filter(|f| f.category != "interrupted" && !args.only_errors)
+    {
+        eprintln!(
+            "{} could not load {}: {}",
+            "warning:".yellow().bold(),
+            failure.url,
+            failure.message
+        );
+    }
+
+    let stats = args.stats.then(|| {
+        let mut stats: Vec<FeedStats> = feed_batches
+            .iter()
+            .map(|(feed_index, batch)| {
+                FeedStats::new(*feed_index, &feeds[*feed_index], batch, current_time(args))
+            })
+            .collect();
+        stats.sort_by_key(|stats| stats.feed_index);
+        stats
+    });
+
+    let feed_items = merge_and_sort(
+        feed_batches.into_iter().map(|(_, batch)| batch).collect(),
+        &MergeOptions::for_args(args),
+    );
+
+    let shown: Vec<&FeedItem> = feed_items.iter().skip(args.offset).take(limit).collect();
+    if args.explain {
+        for item in feed_items.iter().take(args.offset) {
+            explain(item, "skipped", "before the --offset");
+        }
+        for &item in &shown {
+            let passed = filter.passed(item);
+            if passed.is_empty() {
+                explain(item, "shown", "no filters apply");
+            } else {
+                explain(item, "shown", &passed.join(", "));
+            }
+        }
+        for item in feed_items.iter().skip(args.offset).skip(limit) {
+            explain(item, "dropped", &format!("past the --limit of {limit}"));
+        }
+    }
+
+    let output = if args.only_errors {
+        render_failures(&failures, args)?
+    } else if args.metrics {
+        render_metrics(feeds, &metrics)
+    } else if let Some(stats) = stats {
+        render_stats(&stats, args)?
+    } else if args.count_only {
+        format!("{}\n", shown.len())
+    } else if let Some(command) = &args.exec {
+        run_exec(
+            command,
+            render_json(&shown, &failures, &RenderOptions::for_args(args))?,
+        )
+        .await?
+    } else {
+        render(&shown, &failures, &RenderOptions::for_args(args))?
+    };
+    emit(&output, args)?;
+
+    if let Some((_, path)) = &digest_path {
+        // A partial digest is not worth repeating to other callers.
+        if failures.is_empty() {
+            save_digest(path, &output)?;
+        }
+    }
+
+    if args.mark_read {
+        let mut read = ReadState::load(dirs)?;
+        for &item in &shown {
+            read.mark(item);
+        }
+        read.save(dirs)?;
+    }
+
+    // Runs that missed some feeds would skip those feeds' new items next time.
+    if let Some(path) = last_run_path(args, dirs) {
+        if !interrupted && failures.is_empty() {
+            save_last_run(&path, started)?;
+        }
+    }
+
+    // An interrupted run should end as soon as its output is out.
+    let stale_feeds: Vec<&FeedSpec> = feeds
+        .iter()
+        .filter(|feed| feed.cache_mode.unwrap_or(args.cache_mode) == CacheModeArg::Swr)
+        // Files and sockets don't go through the HTTP cache, so there is nothing to refresh.
+        .filter(|feed| {
+            feed.socket.is_none() && Url::parse(&feed.url).is_ok_and(|url| url.scheme() != "file")
+        })
+        // Those that failed just now have already been reported.
+        .filter(|feed| {
+            let url = display_url(&feed.url);
+            !failures.iter().any(|failure| failure.url == url)
+        })
+        .filter(|_| !interrupted)
+        .collect();
+
+    if !stale_feeds.is_empty() {
+        // These were served from the cache no matter how stale, so refresh them now for the next run.
+        std::io::stdout().flush().into_diagnostic()?;
+
+        let mut revalidations = JoinSet::new();
+        for feed in stale_feeds {
+            let request = feed_request(client, feed).with_extension(CacheMode::Default);
+            let url = feed.url.clone();
+            revalidations.spawn(async move {
+                let result = match request.send().await {
+                    Ok(res) if res.status().is_success() => Ok(()),
+                    Ok(res) => Err(FetchError::Status(res.status())),
+                    Err(mut e) => {
+                        if let Some(url) = e.url_mut() {
+                            redact_url(url);
+                        }
+                        Err(FetchError::Request(e))
+                    }
+                };
+                (url, result)
+            });
+        }
+
+        for (url, result) in revalidations.join_all().await {
+            if let Err(error) = result {
+                let failure = FeedFailure::from_error(&url, &error);
+                eprintln!(
+                    "{} could not refresh {} for the next run: {}",
+                    "warning:".yellow().bold(),
+                    failure.url,
+                    failure.message
+                );
+            }
+        }
+    }
+
+    if interrupted {
+        Ok(RunStatus::Interrupted)
+    } else if (args.strict || args.only_errors) && !failures.is_empty()
+        || args.strict && args.count_only && shown.is_empty()
+    {
+        Ok(RunStatus::Failed)
+    } else {
+        Ok(RunStatus::Passed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digest_cache_depends_on_the_arguments_given() {
+        let dirs = Dirs {
+            config_file: PathBuf::from("/config.kdl"),
+            cache_dir: PathBuf::from("/cache"),
+            state_dir: PathBuf::from("/state"),
+        };
+        let feeds = [FeedSpec::new(
+            &Url::parse("https://example.com/feed.xml").unwrap(),
+        )];
+        let path = |argv: &[&str]| {
+            let args = Args::try_parse_from(argv).unwrap();
+            digest_cache_path(&args, &dirs, &feeds)
+        };
+
+        assert_eq!(
+            path(&["dashboard-feeds", "-n", "5"]),
+            path(&["dashboard-feeds", "--limit=5"])
+        );
+        assert_ne!(
+            path(&["dashboard-feeds", "-n", "5"]),
+            path(&["dashboard-feeds", "-n", "6"])
+        );
+    }
+
+    #[test]
+    fn locations_without_standard_directories_must_all_be_given() {
+        let args = Args::try_parse_from(["dashboard-feeds", "--config", "/etc/feeds.kdl"]).unwrap();
+        assert!(Dirs::resolve_with(&args, None).is_err());
+
+        let args = Args::try_parse_from([
+            "dashboard-feeds",
+            "--config",
+            "/etc/feeds.kdl",
+            "--cache-dir",
+            "/var/cache/feeds",
+            "--state-dir",
+            "/var/lib/feeds",
+        ])
+        .unwrap();
+        let dirs = Dirs::resolve_with(&args, None).unwrap();
+        assert_eq!(dirs.config_file, Path::new("/etc/feeds.kdl"));
+        assert_eq!(dirs.cache_dir, Path::new("/var/cache/feeds"));
+        assert_eq!(dirs.state_dir, Path::new("/var/lib/feeds"));
+    }
+
+    #[test]
+    fn locations_not_given_fall_back_to_the_standard_directories() {
+        let Some(project_dirs) = ProjectDirs::from("dev", "cosmicrose", "dashboard-feeds") else {
+            return;
+        };
+        let args =
+            Args::try_parse_from(["dashboard-feeds", "--cache-dir", "/var/cache/feeds"]).unwrap();
+
+        let dirs = Dirs::resolve_with(&args, Some(project_dirs.clone())).unwrap();
+
+        assert_eq!(
+            dirs.config_file,
+            project_dirs.config_dir().join("config.kdl")
+        );
+        assert_eq!(dirs.cache_dir, Path::new("/var/cache/feeds"));
+        assert_eq!(
+            dirs.state_dir,
+            project_dirs
+                .state_dir()
+                .unwrap_or_else(|| project_dirs.data_local_dir())
+        );
+    }
+}
@@ -0,0 +1,1181 @@
+//! Reading, checking, and migrating the feed configuration.
+
+use std::{
+    collections::HashMap,
+    fs,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use clap::ValueEnum;
+use http_cache_reqwest::CacheMode;
+use kdl::{KdlDocument, KdlError, KdlNode, KdlNodeFormat};
+use miette::{bail, miette, Context, Diagnostic, IntoDiagnostic, NamedSource, Result, SourceSpan};
+use owo_colors::OwoColorize;
+use regex::Regex;
+use reqwest::{header::HeaderValue, Url};
+use thiserror::Error;
+
+use crate::{
+    fetch::{decode_entities, display_url, is_insecure_url, same_url},
+    render::ThemeName,
+    write_atomically,
+};
+
+#[derive(Error, Diagnostic, Debug)]
+pub enum ConfigurationError {
+    #[error("Could not read configuration file at {}", path.display())]
+    CouldNotRead {
+        path: PathBuf,
+
+        #[source]
+        cause: std::io::Error,
+    },
+
+    #[error("Could not parse configuration file at {}", source_code.name())]
+    #[diagnostic(help(
+        "Both KDL v2 and v1 syntax are accepted. Start the file with `/- kdl-version 2` (or 1) to pick one and get errors for it"
+    ))]
+    CouldNotParse {
+        #[source_code]
+        source_code: NamedSource<String>,
+
+        #[source]
+        #[diagnostic_source]
+        cause: KdlError,
+    },
+
+    #[error("Configuration node \"feeds\" is missing or doesn't have any entries")]
+    #[diagnostic(help(
+        "Add feeds like this:
+
+    feeds {{
+        url \"https://blog.rust-lang.org/feed.xml\"
+    }}"
+    ))]
+    MissingFeeds {
+        #[source_code]
+        src: NamedSource<String>,
+
+        #[label("missing from here")]
+        span: SourceSpan,
+    },
+
+    #[error("Configured list of feeds has a bad entry")]
+    #[diagnostic(help(
+        "Feed entries should look like this:
+
+    url \"https://blog.rust-lang.org/feed.xml\""
+    ))]
+    InvalidFeedUrl {
+        #[source_code]
+        src: NamedSource<String>,
+
+        #[label("this should have a URL string argument")]
+        span: SourceSpan,
+    },
+
+    #[error("List of ignored feeds has a bad entry")]
+    #[diagnostic(help(
+        "Ignored feeds are listed the same way as configured ones:
+
+    ignore {{
+        url \"https://blog.rust-lang.org/feed.xml\"
+    }}"
+    ))]
+    InvalidIgnoredUrl {
+        #[source_code]
+        src: NamedSource<String>,
+
+        #[label("this should have a URL string argument")]
+        span: SourceSpan,
+    },
+
+    #[error("Setting \"{name}\" has an invalid value")]
+    #[diagnostic(help("The \"{name}\" setting should be {expected}"))]
+    InvalidSetting {
+        name: String,
+        expected: &'static str,
+
+        #[source_code]
+        src: NamedSource<String>,
+
+        #[label("this should be {expected}")]
+        span: SourceSpan,
+    },
+
+    #[error("Feed property \"{name}\" has an invalid value")]
+    #[diagnostic(help("The \"{name}\" property should be {expected}"))]
+    InvalidFeedProperty {
+        name: String,
+        expected: &'static str,
+
+        #[source_code]
+        src: NamedSource<String>,
+
+        #[label("this should be {expected}")]
+        span: SourceSpan,
+    },
+}
+
+/// Holds the whole configuration document, for deployments where a file is inconvenient.
+pub(crate) const CONFIG_ENV_VAR: &str = "DASHBOARD_FEEDS_CONFIG";
+
+/// Properties understood on feed entries.
+const FEED_PROPERTIES: &[&str] = &[
+    "name", "username", "password", "cache", "priority", "accept", "referer", "socket", "limit",
+];
+
+/// Properties understood on groups of feeds.
+const GROUP_PROPERTIES: &[&str] = &["title"];
+
+#[derive(Error, Diagnostic, Debug)]
+pub enum ConfigurationWarning {
+    #[error("Unknown feed property \"{name}\"")]
+    #[diagnostic(
+        severity(Warning),
+        help("Feed entries understand these properties: {}", FEED_PROPERTIES.join(", "))
+    )]
+    UnknownFeedProperty {
+        name: String,
+
+        #[source_code]
+        src: NamedSource<String>,
+
+        #[label("this is ignored")]
+        span: SourceSpan,
+    },
+
+    #[error("Unknown group property \"{name}\"")]
+    #[diagnostic(
+        severity(Warning),
+        help("Groups understand these properties: {}", GROUP_PROPERTIES.join(", "))
+    )]
+    UnknownGroupProperty {
+        name: String,
+
+        #[source_code]
+        src: NamedSource<String>,
+
+        #[label("this is ignored")]
+        span: SourceSpan,
+    },
+
+    #[error("Feed {url} is listed more than once")]
+    #[diagnostic(severity(Warning), help("It will only be fetched once"))]
+    DuplicateFeed {
+        url: String,
+
+        #[source_code]
+        src: NamedSource<String>,
+
+        #[label("first listed here")]
+        first: SourceSpan,
+
+        #[label("listed again here")]
+        span: SourceSpan,
+    },
+
+    #[error("Feed {url} is fetched over plain HTTP")]
+    #[diagnostic(
+        severity(Warning),
+        help("If the site supports it, use an https:// URL so the feed can't be read or altered on the way")
+    )]
+    InsecureFeed {
+        url: String,
+
+        #[source_code]
+        src: NamedSource<String>,
+
+        #[label("not encrypted")]
+        span: SourceSpan,
+    },
+
+    #[error("Ignored feed {url} isn't in the list of feeds")]
+    #[diagnostic(
+        severity(Warning),
+        help("Check that it's spelled the same way as the URL in the \"feeds\" node")
+    )]
+    UnmatchedIgnore {
+        url: String,
+
+        #[source_code]
+        src: NamedSource<String>,
+
+        #[label("matches no feed")]
+        span: SourceSpan,
+    },
+
+    #[error("Feed entries don't have child nodes")]
+    #[diagnostic(severity(Warning))]
+    UnexpectedChildren {
+        #[source_code]
+        src: NamedSource<String>,
+
+        #[label("these are ignored")]
+        span: SourceSpan,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheModeArg {
+    /// Use fresh cached responses and revalidate stale ones
+    Default,
+    /// Ignore the cache entirely
+    NoStore,
+    /// Always fetch from the network, then update the cache
+    Reload,
+    /// Always revalidate cached responses with the server
+    NoCache,
+    /// Use any cached response, no matter how stale
+    ForceCache,
+    /// Use only cached responses, never touching the network
+    OnlyIfCached,
+    /// Show cached responses immediately, then revalidate them for the next run
+    Swr,
+}
+
+impl From<CacheModeArg> for CacheMode {
+    fn from(mode: CacheModeArg) -> Self {
+        match mode {
+            CacheModeArg::Default => CacheMode::Default,
+            CacheModeArg::NoStore => CacheMode::NoStore,
+            CacheModeArg::Reload => CacheMode::Reload,
+            CacheModeArg::NoCache => CacheMode::NoCache,
+            CacheModeArg::ForceCache | CacheModeArg::Swr => CacheMode::ForceCache,
+            CacheModeArg::OnlyIfCached => CacheMode::OnlyIfCached,
+        }
+    }
+}
+
+/// A feed entry from the configuration file.
+#[derive(Clone, Debug)]
+pub struct FeedSpec {
+    pub url: String,
+    pub name: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub cache_mode: Option<CacheModeArg>,
+    /// Sent as the `Accept` header, for servers that pick between HTML and a feed by it.
+    pub accept: Option<String>,
+    /// Sent as the `Referer` header, for servers that only serve visitors from their own pages.
+    pub referer: Option<String>,
+    /// A Unix domain socket to send the request to, instead of connecting to the URL's host.
+    pub socket: Option<PathBuf>,
+    /// Higher priorities sort first among items published at the same time.
+    pub priority: i64,
+    /// The most items this feed contributes, in place of the per-feed limit.
+    pub limit: Option<usize>,
+    /// The name of the configuration group the feed is listed in, if any.
+    pub group: Option<String>,
+    /// The group's `title`, shown in place of its name.
+    pub group_title: Option<String>,
+}
+
+impl FeedSpec {
+    /// A feed given directly by its URL, with nothing else configured.
+    pub fn new(url: &Url) -> Self {
+        FeedSpec {
+            url: url.to_string(),
+            name: None,
+            username: None,
+            password: None,
+            cache_mode: None,
+            accept: None,
+            referer: None,
+            socket: None,
+            priority: 0,
+            limit: None,
+            group: None,
+            group_title: None,
+        }
+    }
+
+    /// The title to show for the feed: its configured name, the title it gives itself,
+    /// or, when that is blank, the host it is fetched from.
+    pub(crate) fn title(&self, feed_title: &str) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+
+        let feed_title = feed_title.trim();
+        if !feed_title.is_empty() {
+            return feed_title.to_string();
+        }
+
+        Url::parse(&self.url)
+            .ok()
+            .and_then(|url| url.host_str().map(String::from))
+            .unwrap_or_else(|| display_url(&self.url))
+    }
+
+    /// Builds the configuration node describing this feed.
+    fn to_kdl_node(&self) -> KdlNode {
+        let mut node = KdlNode::new("url");
+        node.push(self.url.clone());
+
+        if let Some(name) = &self.name {
+            node.push(("name", name.clone()));
+        }
+        if let Some(username) = &self.username {
+            node.push(("username", username.clone()));
+        }
+        if let Some(password) = &self.password {
+            node.push(("password", password.clone()));
+        }
+        if let Some(mode) = self.cache_mode.and_then(|mode| mode.to_possible_value()) {
+            node.push(("cache", mode.get_name()));
+        }
+        if let Some(accept) = &self.accept {
+            node.push(("accept", accept.clone()));
+        }
+        if let Some(referer) = &self.referer {
+            node.push(("referer", referer.clone()));
+        }
+        if let Some(socket) = &self.socket {
+            node.push(("socket", socket.to_string_lossy().into_owned()));
+        }
+        if self.priority != 0 {
+            node.push(("priority", i128::from(self.priority)));
+        }
+        if let Some(limit) = self.limit {
+            node.push(("limit", limit as i128));
+        }
+
+        node
+    }
+}
+
+/// Defaults from the `settings` node of the configuration file.
+#[derive(Default, Debug)]
+pub struct Settings {
+    pub limit: Option<usize>,
+    pub per_feed_limit: Option<usize>,
+    pub theme: Option<ThemeName>,
+    pub jobs: Option<NonZeroUsize>,
+}
+
+/// A configuration file that has been read and parsed, but not yet interpreted.
+pub(crate) struct ConfigFile {
+    /// Where the configuration came from, as shown in diagnostics.
+    name: String,
+    content: String,
+    pub(crate) doc: KdlDocument,
+}
+
+impl ConfigFile {
+    /// Loads the configuration from `DASHBOARD_FEEDS_CONFIG` if it is set,
+    /// or from the configuration file at `path` otherwise.
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        match std::env::var(CONFIG_ENV_VAR) {
+            Ok(content) => Self::parse("<env>", content),
+            Err(_) => Self::read(path),
+        }
+    }
+
+    /// Reads and parses the configuration file at the given path.
+    pub fn read(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            bail!("Config file not found at {}", path.display());
+        };
+
+        let content = fs::read_to_string(path).map_err(|e| ConfigurationError::CouldNotRead {
+            path: path.to_path_buf(),
+            cause: e,
+        })?;
+
+        Self::parse(&path.to_string_lossy(), content)
+    }
+
+    /// Parses a configuration document in either KDL version.
+    ///
+    /// A leading `/- kdl-version 1` or `/- kdl-version 2` comment selects the version;
+    /// otherwise v2 is tried first, falling back to v1.
+    pub fn parse(name: &str, content: String) -> Result<Self> {
+        let version_hint = content
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .and_then(|line| line.strip_prefix("/-"))
+            .and_then(|line| line.trim().strip_prefix("kdl-version"))
+            .map(str::trim);
+
+        let parsed = match version_hint {
+            Some("1") => KdlDocument::parse_v1(&content),
+            Some("2") => KdlDocument::parse_v2(&content),
+            _ => KdlDocument::parse(&content),
+        };
+        let doc = parsed.map_err(|e: KdlError| ConfigurationError::CouldNotParse {
+            source_code: NamedSource::new(name, content.clone()),
+            cause: e,
+        })?;
+
+        Ok(Self {
+            name: name.to_string(),
+            content,
+            doc,
+        })
+    }
+
+    /// The file's contents, for attaching to diagnostics.
+    fn source(&self) -> NamedSource<String> {
+        NamedSource::new(&self.name, self.content.clone())
+    }
+
+    /// Returns the settings, all of which are optional.
+    pub fn settings(&self) -> Result<Settings> {
+        let Some(settings) = self.doc.get("settings").and_then(|node| node.children()) else {
+            return Ok(Settings::default());
+        };
+
+        Ok(Settings {
+            limit: self.count_setting(settings, "limit")?,
+            per_feed_limit: self.count_setting(settings, "per-feed-limit")?,
+            theme: self.theme_setting(settings)?,
+            jobs: self.jobs_setting(settings)?,
+        })
+    }
+
+    /// Reads the optional `jobs` setting, which like --jobs must be at least 1.
+    fn jobs_setting(&self, settings: &KdlDocument) -> Result<Option<NonZeroUsize>> {
+        let Some(jobs) = self.count_setting(settings, "jobs")? else {
+            return Ok(None);
+        };
+
+        match NonZeroUsize::new(jobs) {
+            Some(jobs) => Ok(Some(jobs)),
+            None => bail!(ConfigurationError::InvalidSetting {
+                name: "jobs".to_string(),
+                expected: "a number of feeds to fetch at once, 1 or more",
+                src: self.source(),
+                span: settings.get("jobs").unwrap().span(),
+            }),
+        }
+    }
+
+    /// Reads the optional `theme` setting, which names one of the --theme presets.
+    fn theme_setting(&self, settings: &KdlDocument) -> Result<Option<ThemeName>> {
+        let Some(node) = settings.get("theme") else {
+            return Ok(None);
+        };
+
+        match node
+            .get(0)
+            .and_then(|value| value.as_string())
+            .and_then(|name| ThemeName::from_str(name, true).ok())
+        {
+            Some(theme) => Ok(Some(theme)),
+            None => bail!(ConfigurationError::InvalidSetting {
+                name: "theme".to_string(),
+                expected: "a theme like \"default\", \"mono\", \"vivid\", or \"solarized\"",
+                src: self.source(),
+                span: node.span(),
+            }),
+        }
+    }
+
+    /// Reads an optional setting that takes a single non-negative integer, like `limit 20`.
+    fn count_setting(&self, settings: &KdlDocument, name: &str) -> Result<Option<usize>> {
+        let Some(node) = settings.get(name) else {
+            return Ok(None);
+        };
+
+        match node
+            .get(0)
+            .and_then(|value| value.as_integer())
+            .and_then(|value| usize::try_from(value).ok())
+        {
+            Some(count) => Ok(Some(count)),
+            None => bail!(ConfigurationError::InvalidSetting {
+                name: name.to_string(),
+                expected: "a non-negative whole number",
+                src: self.source(),
+                span: node.span(),
+            }),
+        }
+    }
+
+    /// Returns the list of configured feeds, leaving out any that are ignored.
+    pub fn feeds(&self) -> Result<Vec<FeedSpec>> {
+        let ignored = self.ignored_urls()?;
+
+        Ok(self
+            .listed_feeds()?
+            .into_iter()
+            .filter(|feed| !ignored.iter().any(|url| same_url(&feed.url, url)))
+            .collect())
+    }
+
+    /// Returns every entry of the `feeds` node, including ignored ones.
+    fn listed_feeds(&self) -> Result<Vec<FeedSpec>> {
+        let mut feeds: Vec<FeedSpec> = vec![];
+
+        for (group, node) in self.feed_entries()? {
+            let url = node
+                .get(0)
+                .ok_or(ConfigurationError::InvalidFeedUrl {
+                    src: self.source(),
+                    span: node.span(),
+                })?
+                .as_string()
+                .ok_or(miette!("Configured feed entry is not a string"))?;
+
+            if feeds.iter().any(|feed| feed.url == url) {
+                // Reported by `warnings`.
+                continue;
+            }
+
+            feeds.push(FeedSpec {
+                url: url.to_string(),
+                name: self.string_property(node, "name")?,
+                username: self.string_property(node, "username")?,
+                password: self.string_property(node, "password")?,
+                cache_mode: self.cache_mode_property(node)?,
+                accept: self.accept_property(node)?,
+                referer: self.referer_property(node)?,
+                socket: self.string_property(node, "socket")?.map(PathBuf::from),
+                priority: self.integer_property(node, "priority")?.unwrap_or(0),
+                limit: self.limit_property(node)?,
+                group: group.map(|group| group.name().value().to_string()),
+                group_title: match group {
+                    Some(group) => self.string_property(group, "title")?,
+                    None => None,
+                },
+            });
+        }
+
+        Ok(feeds)
+    }
+
+    /// Returns the entries of the `feeds` node, which must have at least one.
+    fn feed_nodes(&self) -> Result<&[KdlNode]> {
+        let feeds_node = self
+            .doc
+            .get("feeds")
+            .ok_or(ConfigurationError::MissingFeeds {
+                src: self.source(),
+                span: self.doc.span(),
+            })?;
+
+        let feed_nodes = feeds_node
+            .children()
+            .ok_or(ConfigurationError::MissingFeeds {
+                src: self.source(),
+                span: self.doc.span(),
+            })?
+            .nodes();
+
+        if feed_nodes.is_empty() {
+            bail!(ConfigurationError::MissingFeeds {
+                src: self.source(),
+                span: self.doc.span(),
+            });
+        }
+
+        Ok(feed_nodes)
+    }
+
+    /// Returns every feed entry along with the group it is listed in, if any.
+    ///
+    /// A group is an entry of `feeds` without a URL whose children are feed entries,
+    /// like `news title="News" { url "…" }`.
+    fn feed_entries(&self) -> Result<Vec<(Option<&KdlNode>, &KdlNode)>> {
+        let mut entries = vec![];
+
+        for node in self.feed_nodes()? {
+            match node.children().filter(|_| is_group_node(node)) {
+                Some(children) => {
+                    entries.extend(children.nodes().iter().map(|feed| (Some(node), feed)))
+                }
+                None => entries.push((None, node)),
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Returns the entries of every `ignore` node.
+    ///
+    /// There can be more than one, so that a file appended to a shared configuration
+    /// can add its own without touching the rest.
+    fn ignore_nodes(&self) -> impl Iterator<Item = &KdlNode> {
+        self.doc
+            .nodes()
+            .iter()
+            .filter(|node| node.name().value() == "ignore")
+            .filter_map(|node| node.children())
+            .flat_map(|children| children.nodes())
+    }
+
+    /// Returns the URLs listed under `ignore`, which are left out of `feeds`.
+    fn ignored_urls(&self) -> Result<Vec<&str>> {
+        self.ignore_nodes()
+            .map(|node| {
+                node.get(0).and_then(|url| url.as_string()).ok_or_else(|| {
+                    ConfigurationError::InvalidIgnoredUrl {
+                        src: self.source(),
+                        span: node.span(),
+                    }
+                    .into()
+                })
+            })
+            .collect()
+    }
+
+    /// Finds things in feed entries that would otherwise be silently ignored.
+    pub fn warnings(&self) -> Vec<ConfigurationWarning> {
+        let mut warnings = vec![];
+        let mut first_listed: HashMap<&str, SourceSpan> = HashMap::new();
+
+        for node in self.feed_nodes().unwrap_or_default() {
+            if !is_group_node(node) {
+                continue;
+            }
+
+            for entry in node.entries() {
+                let Some(name) = entry.name() else {
+                    continue;
+                };
+
+                if !GROUP_PROPERTIES.contains(&name.value()) {
+                    warnings.push(ConfigurationWarning::UnknownGroupProperty {
+                        name: name.value().to_string(),
+                        src: self.source(),
+                        span: entry.span(),
+                    });
+                }
+            }
+        }
+
+        for (_, node) in self.feed_entries().unwrap_or_default() {
+            if let Some(url) = node.get(0).and_then(|url| url.as_string()) {
+                if let Some(&first) = first_listed.get(url) {
+                    warnings.push(ConfigurationWarning::DuplicateFeed {
+                        url: display_url(url),
+                        src: self.source(),
+                        first,
+                        span: node.span(),
+                    });
+                } else {
+                    first_listed.insert(url, node.span());
+                }
+
+                // Requests over a socket never leave the machine.
+                if is_insecure_url(url) && node.entry("socket").is_none() {
+                    warnings.push(ConfigurationWarning::InsecureFeed {
+                        url: display_url(url),
+                        src: self.source(),
+                        span: node.entry(0).map_or(node.span(), |entry| entry.span()),
+                    });
+                }
+            }
+
+            for entry in node.entries() {
+                let Some(name) = entry.name() else {
+                    continue;
+                };
+
+                if !FEED_PROPERTIES.contains(&name.value()) {
+                    warnings.push(ConfigurationWarning::UnknownFeedProperty {
+                        name: name.value().to_string(),
+                        src: self.source(),
+                        span: entry.span(),
+                    });
+                }
+            }
+
+            if let Some(children) = node.children() {
+                warnings.push(ConfigurationWarning::UnexpectedChildren {
+                    src: self.source(),
+                    span: children.span(),
+                });
+            }
+        }
+
+        for node in self.ignore_nodes() {
+            let Some(url) = node.get(0).and_then(|url| url.as_string()) else {
+                continue;
+            };
+
+            if !first_listed.keys().any(|listed| same_url(listed, url)) {
+                warnings.push(ConfigurationWarning::UnmatchedIgnore {
+                    url: display_url(url),
+                    src: self.source(),
+                    span: node.span(),
+                });
+            }
+        }
+
+        warnings
+    }
+
+    /// Reads the optional `cache` property, which overrides `--cache-mode` for one feed.
+    fn cache_mode_property(&self, node: &KdlNode) -> Result<Option<CacheModeArg>> {
+        let Some(mode) = self.string_property(node, "cache")? else {
+            return Ok(None);
+        };
+
+        match CacheModeArg::from_str(&mode, true) {
+            Ok(mode) => Ok(Some(mode)),
+            Err(_) => bail!(ConfigurationError::InvalidFeedProperty {
+                name: "cache".to_string(),
+                expected: "a cache mode like \"default\", \"reload\", or \"no-store\"",
+                src: self.source(),
+                span: node.entry("cache").unwrap().span(),
+            }),
+        }
+    }
+
+    /// Reads the optional `limit` property, which must be a number of items.
+    fn limit_property(&self, node: &KdlNode) -> Result<Option<usize>> {
+        let Some(entry) = node.entry("limit") else {
+            return Ok(None);
+        };
+
+        match entry
+            .value()
+            .as_integer()
+            .and_then(|value| usize::try_from(value).ok())
+        {
+            Some(limit) => Ok(Some(limit)),
+            None => bail!(ConfigurationError::InvalidFeedProperty {
+                name: "limit".to_string(),
+                expected: "a whole number of items",
+                src: self.source(),
+                span: entry.span(),
+            }),
+        }
+    }
+
+    /// Reads the optional `referer` property, which must be a URL.
+    fn referer_property(&self, node: &KdlNode) -> Result<Option<String>> {
+        let Some(referer) = self.string_property(node, "referer")? else {
+            return Ok(None);
+        };
+
+        match Url::parse(&referer) {
+            Ok(_) => Ok(Some(referer)),
+            Err(_) => bail!(ConfigurationError::InvalidFeedProperty {
+                name: "referer".to_string(),
+                expected: "a URL like \"https://example.com/\"",
+                src: self.source(),
+                span: node.entry("referer").unwrap().span(),
+            }),
+        }
+    }
+
+    /// Reads the optional `accept` property, which must be a list of media types.
+    fn accept_property(&self, node: &KdlNode) -> Result<Option<String>> {
+        let Some(accept) = self.string_property(node, "accept")? else {
+            return Ok(None);
+        };
+
+        let is_media_type = |range: &str| {
+            let essence = range.split(';').next().unwrap_or_default().trim();
+            essence.split_once('/').is_some_and(|(kind, subtype)| {
+                [kind, subtype].iter().all(|part| {
+                    !part.is_empty()
+                        && part
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || "!#$&^_.+-*".contains(c))
+                })
+            })
+        };
+
+        if HeaderValue::from_str(&accept).is_ok() && accept.split(',').all(is_media_type) {
+            Ok(Some(accept))
+        } else {
+            bail!(ConfigurationError::InvalidFeedProperty {
+                name: "accept".to_string(),
+                expected:
+                    "a media type like \"application/atom+xml\", or several separated by commas",
+                src: self.source(),
+                span: node.entry("accept").unwrap().span(),
+            })
+        }
+    }
+
+    /// Reads an optional integer property from a node.
+    fn integer_property(&self, node: &KdlNode, name: &str) -> Result<Option<i64>> {
+        let Some(entry) = node.entry(name) else {
+            return Ok(None);
+        };
+
+        match entry
+            .value()
+            .as_integer()
+            .and_then(|value| i64::try_from(value).ok())
+        {
+            Some(value) => Ok(Some(value)),
+            None => bail!(ConfigurationError::InvalidFeedProperty {
+                name: name.to_string(),
+                expected: "a whole number",
+                src: self.source(),
+                span: entry.span(),
+            }),
+        }
+    }
+
+    /// Reads an optional string property from a node.
+    fn string_property(&self, node: &KdlNode, name: &str) -> Result<Option<String>> {
+        let Some(entry) = node.entry(name) else {
+            return Ok(None);
+        };
+
+        match entry.value().as_string() {
+            Some(value) => Ok(Some(value.to_string())),
+            None => bail!(ConfigurationError::InvalidFeedProperty {
+                name: name.to_string(),
+                expected: "a string",
+                src: self.source(),
+                span: entry.span(),
+            }),
+        }
+    }
+}
+
+/// Rewrites an existing configuration file into the current recommended structure,
+/// keeping a copy of the original next to it.
+pub(crate) fn migrate_config(config_path: &Path) -> Result<()> {
+    let (config_doc, dropped) = migrate_document(ConfigFile::read(config_path)?)?;
+    for url in dropped {
+        eprintln!(
+            "{} dropped a second entry for {url}",
+            "warning:".yellow().bold()
+        );
+    }
+
+    let backup_path = config_path.with_extension("kdl.bak");
+    fs::copy(config_path, &backup_path).into_diagnostic()?;
+    write_atomically(config_path, config_doc.to_string().as_bytes()).into_diagnostic()?;
+
+    println!(
+        "Migrated {} (original saved to {})",
+        config_path.display(),
+        backup_path.display()
+    );
+
+    Ok(())
+}
+
+/// Whether an entry of `feeds` is a group of feeds rather than a feed:
+/// it has child nodes but no URL.
+pub(crate) fn is_group_node(node: &KdlNode) -> bool {
+    node.get(0).is_none() && node.children().is_some()
+}
+
+/// Brings a configuration document into the current structure.
+///
+/// Feed entries are rewritten with their properties in the usual order, keeping any
+/// properties this version doesn't know, child nodes, and comments around them.
+/// Entries repeating an earlier feed's URL are dropped, and their URLs returned
+/// alongside the document so the caller can warn about them.
+fn migrate_document(config: ConfigFile) -> Result<(KdlDocument, Vec<String>)> {
+    // Ignored feeds stay listed, since the `ignore` node is kept as it is.
+    let feeds = config.listed_feeds()?;
+    let original_nodes = config.feed_nodes()?.to_vec();
+    let mut config_doc = config.doc;
+
+    if config_doc.get("settings").is_none() {
+        let mut settings_node = KdlNode::new("settings");
+        settings_node.set_children(KdlDocument::new());
+        config_doc.nodes_mut().insert(0, settings_node);
+    }
+
+    let mut written: Vec<String> = vec![];
+    let mut dropped = vec![];
+    let mut migrate_feed = |original: &KdlNode| {
+        let url = original.get(0).and_then(|url| url.as_string())?;
+        if written.iter().any(|seen| *seen == url) {
+            dropped.push(display_url(url));
+            return None;
+        }
+        written.push(url.to_string());
+        let feed = feeds.iter().find(|feed| feed.url == url)?;
+
+        let mut node = feed.to_kdl_node();
+        for entry in original.entries() {
+            if entry
+                .name()
+                .is_some_and(|name| !FEED_PROPERTIES.contains(&name.value()))
+            {
+                let mut entry = entry.clone();
+                entry.clear_format();
+                node.push(entry);
+            }
+        }
+        if let Some(children) = original.children() {
+            node.set_children(children.clone());
+        }
+        keep_surrounding_comments(original, &mut node);
+        Some(node)
+    };
+
+    let mut feed_nodes = vec![];
+    for original in &original_nodes {
+        if !is_group_node(original) {
+            feed_nodes.extend(migrate_feed(original));
+            continue;
+        }
+
+        // Groups keep their name and properties, with their feeds migrated in turn.
+        let mut group = original.clone();
+        if let Some(children) = group.children_mut() {
+            let migrated = children
+                .nodes()
+                .iter()
+                .filter_map(&mut migrate_feed)
+                .collect();
+            *children.nodes_mut() = migrated;
+        }
+        feed_nodes.push(group);
+    }
+
+    if let Some(feeds_node) = config_doc.get_mut("feeds") {
+        // Keeps comments after the last entry, which belong to the block itself.
+        let mut feeds_children = feeds_node.children().cloned().unwrap_or_default();
+        *feeds_children.nodes_mut() = feed_nodes;
+        feeds_node.set_children(feeds_children);
+    }
+
+    // Older configurations may be KDL v1, so this also brings them up to v2.
+    config_doc.ensure_v2();
+    config_doc.autoformat();
+
+    Ok((config_doc, dropped))
+}
+
+/// Copies the comments and blank lines before and after a node onto its replacement.
+fn keep_surrounding_comments(original: &KdlNode, node: &mut KdlNode) {
+    if let Some(format) = original.format() {
+        node.set_format(KdlNodeFormat {
+            leading: format.leading.clone(),
+            trailing: format.trailing.clone(),
+            ..KdlNodeFormat::default()
+        });
+    }
+}
+
+/// Reads the configuration file at `path`, returning its settings and the feeds it lists,
+/// leaving out ignored ones.
+///
+/// Anything in the file that would be silently ignored isn't reported; `dashboard-feeds lint`
+/// checks for that.
+pub fn load_config(path: &Path) -> Result<(Settings, Vec<FeedSpec>)> {
+    let config = ConfigFile::read(path)?;
+    Ok((config.settings()?, config.feeds()?))
+}
+
+/// Reads the feeds listed in an OPML subscription list, named by their outlines.
+pub(crate) fn read_opml(path: &Path) -> Result<Vec<FeedSpec>> {
+    let content = fs::read_to_string(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Could not read {}", path.display()))?;
+    static COMMENT: LazyLock<Regex> =
+        LazyLock::new(|| Regex::new(r"(?s)<!--.*?-->").expect("the comment pattern is valid"));
+    // Attribute values may contain `>`, so they are skipped over whole.
+    static OUTLINE: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r#"<outline\b((?:[^>"']|"[^"]*"|'[^']*')*)>"#)
+            .expect("the outline pattern is valid")
+    });
+    static ATTRIBUTE: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r#"([\w:.-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#)
+            .expect("the attribute pattern is valid")
+    });
+
+    let content = COMMENT.replace_all(&content, "");
+    let mut feeds: Vec<FeedSpec> = vec![];
+    for outline in OUTLINE.captures_iter(&content) {
+        let mut url = None;
+        let mut title = None;
+        let mut text = None;
+        for attribute in ATTRIBUTE.captures_iter(&outline[1]) {
+            let value = attribute
+                .get(2)
+                .or_else(|| attribute.get(3))
+                .map_or(String::new(), |value| decode_entities(value.as_str()));
+            match &attribute[1] {
+                "xmlUrl" => url = Some(value),
+                "title" => title = Some(value),
+                "text" => text = Some(value),
+                _ => {}
+            }
+        }
+
+        // Outlines without a feed URL are folders.
+        match url.map(|url| (Url::parse(&url), url)) {
+            Some((Ok(url), _)) if feeds.iter().all(|feed| feed.url != url.as_str()) => {
+                feeds.push(FeedSpec {
+                    name: [title, text]
+                        .into_iter()
+                        .flatten()
+                        .find(|name| !name.trim().is_empty()),
+                    ..FeedSpec::new(&url)
+                });
+            }
+            Some((Err(e), url)) => eprintln!(
+                "{} skipping {url} from the OPML file: {e}",
+                "warning:".yellow().bold()
+            ),
+            _ => {}
+        }
+    }
+
+    if feeds.is_empty() {
+        bail!(
+            help = "OPML lists feeds as <outline> elements with an xmlUrl attribute",
+            "{} doesn't list any feeds",
+            path.display()
+        );
+    }
+
+    Ok(feeds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migration_keeps_comments_and_unknown_properties() {
+        let config = ConfigFile::parse(
+            "test.kdl",
+            r#"// My feeds
+feeds {
+    // The Rust blog
+    url "https://blog.rust-lang.org/feed.xml" colour="red"
+    url "https://blog.rust-lang.org/feed.xml"
+    url "https://archlinux.org/feeds/news/" name="Arch"
+}
+"#
+            .to_string(),
+        )
+        .unwrap();
+
+        let migrated = migrate_document(config).unwrap().0.to_string();
+
+        assert!(migrated.contains("// My feeds"));
+        assert!(migrated.contains("// The Rust blog"));
+        assert!(migrated.contains("colour=red"));
+        assert!(migrated.contains("settings"));
+        assert_eq!(migrated.matches("blog.rust-lang.org").count(), 1);
+    }
+
+    const GROUPED_CONFIG: &str = r#"feeds {
+    url "https://blog.rust-lang.org/feed.xml"
+    news title="Tech News" {
+        url "https://lwn.net/headlines/rss" name="LWN"
+    }
+    hobbies {
+        url "https://archlinux.org/feeds/news/"
+    }
+}
+"#;
+
+    #[test]
+    fn groups_carry_their_name_and_title_to_their_feeds() {
+        let config = ConfigFile::parse("test.kdl", GROUPED_CONFIG.to_string()).unwrap();
+        let feeds = config.feeds().unwrap();
+
+        let groups: Vec<_> = feeds
+            .iter()
+            .map(|feed| (feed.group.as_deref(), feed.group_title.as_deref()))
+            .collect();
+        assert_eq!(
+            groups,
+            [
+                (None, None),
+                (Some("news"), Some("Tech News")),
+                (Some("hobbies"), None)
+            ]
+        );
+        assert!(config.warnings().is_empty());
+    }
+
+    #[test]
+    fn migration_keeps_groups() {
+        let config = ConfigFile::parse("test.kdl", GROUPED_CONFIG.to_string()).unwrap();
+
+        let migrated =
+            ConfigFile::parse("test.kdl", migrate_document(config).unwrap().0.to_string()).unwrap();
+
+        let feeds = migrated.feeds().unwrap();
+        assert_eq!(feeds.len(), 3);
+        assert_eq!(feeds[1].group_title.as_deref(), Some("Tech News"));
+        assert_eq!(feeds[1].name.as_deref(), Some("LWN"));
+    }
+
+    #[test]
+    fn migration_returns_the_duplicate_entries_it_drops() {
+        let config = ConfigFile::parse(
+            "test.kdl",
+            r#"feeds {
+    url "https://example.com/feed.xml" name="First"
+    url "https://example.com/feed.xml" name="Second"
+}"#
+            .to_string(),
+        )
+        .unwrap();
+
+        let (doc, dropped) = migrate_document(config).unwrap();
+
+        assert_eq!(dropped, ["https://example.com/feed.xml"]);
+        let migrated = ConfigFile::parse("test.kdl", doc.to_string()).unwrap();
+        assert_eq!(migrated.feeds().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn configuration_loads_from_a_path() {
+        let path = std::env::temp_dir().join(format!("dashboard-feeds-{}.kdl", std::process::id()));
+        fs::write(&path, GROUPED_CONFIG).unwrap();
+
+        let (settings, feeds) = load_config(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(settings.limit, None);
+        assert_eq!(feeds.len(), 3);
+    }
+
+    #[test]
+    fn opml_outlines_with_a_feed_url_are_read() {
+        let path =
+            std::env::temp_dir().join(format!("dashboard-feeds-{}.opml", std::process::id()));
+        fs::write(
+            &path,
+            r#"<?xml version="1.0"?>
+<opml version="2.0">
+  <body>
+    <outline text="News">
+      <outline text="Q&amp;A" title="Q &gt; A" type="rss" xmlUrl="https://example.com/feed?a=1&amp;b=2"/>
+      <!-- <outline text="Old" xmlUrl="https://old.example.com/feed"/> -->
+      <outline text='Single' xmlUrl='https://example.org/rss.xml'></outline>
+    </outline>
+  </body>
+</opml>"#,
+        )
+        .unwrap();
+
+        let feeds = read_opml(&path);
+        fs::remove_file(&path).unwrap();
+
+        let feeds: Vec<_> = feeds
+            .unwrap()
+            .into_iter()
+            .map(|feed| (feed.url, feed.name.unwrap()))
+            .collect();
+        assert_eq!(
+            feeds,
+            [
+                (
+                    "https://example.com/feed?a=1&b=2".to_string(),
+                    "Q > A".to_string()
+                ),
+                (
+                    "https://example.org/rss.xml".to_string(),
+                    "Single".to_string()
+                ),
+            ]
+        );
+    }
+}
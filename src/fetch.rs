@@ -0,0 +1,1714 @@
+//! Fetching feeds over HTTP and turning their entries into [`FeedItem`]s.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::Read,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+    time::Duration,
+};
+
+use chrono::prelude::*;
+use encoding_rs::{Encoding, UTF_8};
+use flate2::read::GzDecoder;
+use http_cache_reqwest::CacheMode;
+use miette::{Diagnostic, Result};
+use regex::{Regex, RegexBuilder};
+use reqwest::{
+    header::{ACCEPT, CONTENT_ENCODING, CONTENT_TYPE, HOST, REFERER, RETRY_AFTER},
+    Response, StatusCode, Url,
+};
+use reqwest_middleware::{ClientWithMiddleware, RequestBuilder};
+use rss::extension::syndication::UpdatePeriod;
+use serde::Serialize;
+use syndication::Feed;
+use thiserror::Error;
+use tokio::task::JoinError;
+
+use crate::{config::FeedSpec, stable_item_id, FeedItem};
+
+/// The first bytes of every gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// The header the HTTP cache sets to `HIT` on responses it served itself.
+const XCACHE: &str = "x-cache";
+
+/// The longest we are willing to wait when a server asks us to retry later.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// Sent instead of our own user agent with --browser-ua, for servers that turn away anything else.
+pub(crate) const BROWSER_USER_AGENT: &str =
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:128.0) Gecko/20100101 Firefox/128.0";
+
+/// Builds the default user agent, linking to the project homepage if one is set.
+pub(crate) fn default_user_agent() -> String {
+    let product = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+    match option_env!("CARGO_PKG_HOMEPAGE") {
+        Some(homepage) if !homepage.is_empty() => format!("{product} +{homepage}"),
+        _ => product.to_string(),
+    }
+}
+
+#[derive(Error, Diagnostic, Debug)]
+pub enum FetchError {
+    #[error("Could not fetch feed")]
+    Request(#[source] reqwest_middleware::Error),
+
+    #[error("Server responded with {0}")]
+    Status(StatusCode),
+
+    #[error("Could not read feed response")]
+    Body(#[source] reqwest::Error),
+
+    #[error("Could not read feed file")]
+    File(#[source] std::io::Error),
+
+    #[error("Could not fetch feed over its socket")]
+    Socket(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+    #[error("Could not decompress feed")]
+    Decompress(#[source] std::io::Error),
+
+    #[error("Could not parse feed: {0}")]
+    Parse(&'static str),
+
+    #[error("Server sent an empty feed")]
+    Empty,
+
+    #[error("Server redirected more times than allowed")]
+    #[diagnostic(help("--max-redirects sets how many redirects to follow"))]
+    TooManyRedirects,
+}
+
+/// Something worth telling about a feed that was fetched all the same.
+#[derive(Error, Debug)]
+pub enum FetchNote {
+    #[error("{feed} doesn't parse, so --recover scraped {items} items out of it")]
+    Recovered { feed: String, items: usize },
+
+    #[error("found a feed for {feed} at {url}")]
+    Probed { feed: String, url: String },
+
+    #[error("could not fetch the next page of {feed} from {url}: {error}")]
+    NextPageFailed {
+        feed: String,
+        url: String,
+        error: FetchError,
+    },
+
+    #[error("could not save the response to {}: {error}", path.display())]
+    DumpFailed {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+}
+
+impl FetchNote {
+    /// Whether the note is about something that went wrong, rather than just news.
+    pub fn is_warning(&self) -> bool {
+        matches!(
+            self,
+            FetchNote::NextPageFailed { .. } | FetchNote::DumpFailed { .. }
+        )
+    }
+}
+
+impl FetchError {
+    /// A short machine-readable name for the kind of failure.
+    fn category(&self) -> &'static str {
+        match self {
+            FetchError::Request(_) | FetchError::Body(_) | FetchError::Socket(_) => "network",
+            FetchError::Status(_) | FetchError::TooManyRedirects => "http",
+            FetchError::File(_) => "file",
+            FetchError::Decompress(_) | FetchError::Parse(_) | FetchError::Empty => "parse",
+        }
+    }
+}
+
+/// A feed that could not be loaded.
+#[derive(Serialize)]
+pub(crate) struct FeedFailure {
+    pub url: String,
+    /// What kind of failure it was, like `http` or `parse`.
+    pub category: &'static str,
+    pub message: String,
+}
+
+impl FeedFailure {
+    pub(crate) fn new(url: &str, category: &'static str, message: String) -> Self {
+        Self {
+            url: display_url(url),
+            category,
+            message,
+        }
+    }
+
+    pub fn from_error(url: &str, error: &FetchError) -> Self {
+        let mut message = error.to_string();
+        let mut source = std::error::Error::source(error);
+        while let Some(cause) = source {
+            let cause_message = cause.to_string();
+            if !message.contains(&cause_message) {
+                message = format!("{message}: {cause_message}");
+            }
+            source = cause.source();
+        }
+
+        Self::new(url, error.category(), message)
+    }
+
+    pub(crate) fn from_join_error(url: &str, error: JoinError) -> Self {
+        if error.is_cancelled() {
+            return Self::new(
+                url,
+                "timeout",
+                "did not finish before the deadline".to_string(),
+            );
+        }
+
+        let message = match error.try_into_panic() {
+            Ok(payload) => payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "feed processing panicked".to_string()),
+            Err(_) => "feed processing failed".to_string(),
+        };
+
+        Self::new(url, "internal", message)
+    }
+}
+
+/// Builds the request for fetching a feed.
+pub(crate) fn feed_request(client: &ClientWithMiddleware, feed: &FeedSpec) -> RequestBuilder {
+    let mut request = client.get(&feed.url);
+    if let Some(username) = &feed.username {
+        request = request.basic_auth(username, feed.password.as_ref());
+    }
+    if let Some(mode) = feed.cache_mode {
+        request = request.with_extension(CacheMode::from(mode));
+    }
+    if let Some(accept) = &feed.accept {
+        request = request.header(ACCEPT, accept);
+    }
+    if let Some(referer) = &feed.referer {
+        request = request.header(REFERER, referer);
+    }
+
+    request
+}
+
+/// Sends the request for a feed.
+///
+/// If the server responds with `429 Too Many Requests` and says when to come back,
+/// this waits that long (up to [`MAX_RETRY_AFTER`]) and tries once more.
+pub(crate) async fn send_feed_request(
+    client: &ClientWithMiddleware,
+    feed: &FeedSpec,
+) -> Result<Response, FetchError> {
+    let mut res = send_request_once(client, feed).await?;
+
+    if res.status() == StatusCode::TOO_MANY_REQUESTS {
+        if let Some(wait) = retry_after(&res) {
+            tokio::time::sleep(wait.min(MAX_RETRY_AFTER)).await;
+            res = send_request_once(client, feed).await?;
+        }
+    }
+
+    if !res.status().is_success() {
+        return Err(FetchError::Status(res.status()));
+    }
+
+    Ok(res)
+}
+
+/// Whether a request failed because it ran into the redirect limit.
+fn is_redirect_error(error: &reqwest_middleware::Error) -> bool {
+    match error {
+        reqwest_middleware::Error::Reqwest(e) => e.is_redirect(),
+        // The HTTP cache passes on errors from the request it sends as its own.
+        reqwest_middleware::Error::Middleware(e) => e.chain().any(|e| {
+            e.downcast_ref::<reqwest::Error>()
+                .is_some_and(reqwest::Error::is_redirect)
+                || e.downcast_ref::<reqwest_middleware::Error>()
+                    .is_some_and(is_redirect_error)
+        }),
+    }
+}
+
+async fn send_request_once(
+    client: &ClientWithMiddleware,
+    feed: &FeedSpec,
+) -> Result<Response, FetchError> {
+    let request_error = |mut e: reqwest_middleware::Error| {
+        if is_redirect_error(&e) {
+            return FetchError::TooManyRedirects;
+        }
+        if let Some(url) = e.url_mut() {
+            redact_url(url);
+        }
+        FetchError::Request(e)
+    };
+
+    if let Some(path) = Url::parse(&feed.url)
+        .ok()
+        .filter(|url| url.scheme() == "file")
+        .and_then(|url| url.to_file_path().ok())
+    {
+        return read_feed_file(&path).await;
+    }
+
+    match &feed.socket {
+        Some(socket) => {
+            let request = feed_request(client, feed)
+                .build()
+                .map_err(|e| request_error(e.into()))?;
+            send_over_socket(socket, request).await
+        }
+        None => feed_request(client, feed)
+            .send()
+            .await
+            .map_err(request_error),
+    }
+}
+
+/// Reads a feed from a `file://` URL as though a server had sent it.
+///
+/// Files ending in `.gz` are decompressed first.
+async fn read_feed_file(path: &Path) -> Result<Response, FetchError> {
+    let mut bytes = tokio::fs::read(path).await.map_err(FetchError::File)?;
+
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        let mut decompressed = vec![];
+        GzDecoder::new(bytes.as_slice())
+            .read_to_end(&mut decompressed)
+            .map_err(FetchError::Decompress)?;
+        bytes = decompressed;
+    }
+
+    Ok(Response::from(hyper::Response::new(bytes)))
+}
+
+/// Sends a request to an HTTP server listening on a Unix domain socket.
+///
+/// This goes around the client, so these feeds don't use the HTTP cache.
+#[cfg(unix)]
+async fn send_over_socket(
+    socket: &Path,
+    request: reqwest::Request,
+) -> Result<Response, FetchError> {
+    use http_body_util::BodyExt;
+
+    fn socket_error(e: impl std::error::Error + Send + Sync + 'static) -> FetchError {
+        FetchError::Socket(Box::new(e))
+    }
+
+    let stream = tokio::net::UnixStream::connect(socket)
+        .await
+        .map_err(socket_error)?;
+    let (mut sender, connection) =
+        hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream))
+            .await
+            .map_err(socket_error)?;
+    tokio::spawn(connection);
+
+    let url = request.url();
+    let mut path_and_query = url.path().to_string();
+    if let Some(query) = url.query() {
+        path_and_query.push('?');
+        path_and_query.push_str(query);
+    }
+
+    let mut builder = hyper::Request::get(path_and_query);
+    if let Some(host) = url.host_str() {
+        builder = builder.header(HOST, host);
+    }
+    for (name, value) in request.headers() {
+        builder = builder.header(name, value);
+    }
+    let hyper_request = builder
+        .body(http_body_util::Empty::<hyper::body::Bytes>::new())
+        .map_err(socket_error)?;
+
+    let (parts, body) = sender
+        .send_request(hyper_request)
+        .await
+        .map_err(socket_error)?
+        .into_parts();
+    let body = body.collect().await.map_err(socket_error)?.to_bytes();
+
+    Ok(Response::from(hyper::Response::from_parts(parts, body)))
+}
+
+#[cfg(not(unix))]
+async fn send_over_socket(
+    _socket: &Path,
+    _request: reqwest::Request,
+) -> Result<Response, FetchError> {
+    Err(FetchError::Socket(Box::new(std::io::Error::from(
+        std::io::ErrorKind::Unsupported,
+    ))))
+}
+
+/// Reads a feed response as text.
+pub(crate) async fn read_feed_body(res: Response) -> Result<String, FetchError> {
+    RawBody::read(res).await?.decode()
+}
+
+/// A response body as the server sent it, with what its headers say about decoding it.
+struct RawBody {
+    bytes: Vec<u8>,
+    /// Whether the server named a `Content-Encoding`, so the body isn't sniffed for gzip.
+    labeled_encoding: bool,
+    encoding: &'static Encoding,
+}
+
+impl RawBody {
+    /// Reads the whole body of a response.
+    async fn read(res: Response) -> Result<Self, FetchError> {
+        let labeled_encoding = res.headers().contains_key(CONTENT_ENCODING);
+        let encoding = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| {
+                value
+                    .split(';')
+                    .filter_map(|param| param.trim().split_once('='))
+                    .find(|(name, _)| name.eq_ignore_ascii_case("charset"))
+                    .and_then(|(_, charset)| {
+                        Encoding::for_label(charset.trim_matches('"').as_bytes())
+                    })
+            })
+            .unwrap_or(UTF_8);
+
+        let bytes = res
+            .bytes()
+            .await
+            .map_err(|mut e| {
+                if let Some(url) = e.url_mut() {
+                    redact_url(url);
+                }
+                FetchError::Body(e)
+            })?
+            .to_vec();
+
+        Ok(RawBody {
+            bytes,
+            labeled_encoding,
+            encoding,
+        })
+    }
+
+    /// Turns the body into text.
+    ///
+    /// Some servers gzip feeds without saying so in `Content-Encoding`, which would otherwise
+    /// reach the parser as binary garbage, so such bodies are decompressed here.
+    fn decode(self) -> Result<String, FetchError> {
+        let mut bytes = self.bytes;
+        if !self.labeled_encoding && bytes.starts_with(&GZIP_MAGIC) {
+            let mut decompressed = vec![];
+            GzDecoder::new(bytes.as_slice())
+                .read_to_end(&mut decompressed)
+                .map_err(FetchError::Decompress)?;
+            bytes = decompressed;
+        }
+
+        // Decoding sniffs and drops a leading byte-order mark, but some feeds
+        // repeat it or re-encode it, and the XML parser refuses anything before `<?xml`.
+        let (text, _, _) = self.encoding.decode(&bytes);
+        Ok(text.trim_start_matches('\u{feff}').to_string())
+    }
+}
+
+/// Reads a `Retry-After` header, which is either a number of seconds or an HTTP date.
+fn retry_after(res: &Response) -> Option<Duration> {
+    let value = res.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (at.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// Whether a feed URL uses plain HTTP to reach another machine.
+pub(crate) fn is_insecure_url(url: &str) -> bool {
+    let Ok(url) = Url::parse(url) else {
+        return false;
+    };
+
+    url.scheme() == "http"
+        && url.host_str().is_some_and(|host| {
+            let loopback = host.eq_ignore_ascii_case("localhost")
+                || host
+                    .trim_matches(['[', ']'])
+                    .parse::<IpAddr>()
+                    .is_ok_and(|ip| ip.is_loopback());
+            !loopback
+        })
+}
+
+/// Formats a configured URL for display, without any credentials it contains.
+pub(crate) fn display_url(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(mut url) => {
+            redact_url(&mut url);
+            url.to_string()
+        }
+        Err(_) => url.to_string(),
+    }
+}
+
+/// Turns a URL into a file name for --dump-raw.
+fn dump_file_name(url: &str) -> String {
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let name: String = url
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-_.".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    format!("{}.raw", name.trim_matches('_'))
+}
+
+/// Strips any credentials embedded in a URL, so that it is safe to show.
+pub(crate) fn redact_url(url: &mut Url) {
+    let _ = url.set_username("");
+    let _ = url.set_password(None);
+}
+
+/// The title an Atom feed gives itself, or its subtitle if the title is missing or blank.
+fn atom_title(feed: &atom_syndication::Feed) -> &str {
+    match feed.title().trim() {
+        "" => feed.subtitle().unwrap_or("").trim(),
+        title => title,
+    }
+}
+
+/// Picks the page an Atom entry is about.
+///
+/// Prefers the `alternate` link, then any link, then an `id` that is itself a web URL.
+/// Entries with none of these get an empty link.
+fn atom_link(entry: &atom_syndication::Entry) -> String {
+    let links = entry.links();
+    let links: Vec<_> = links
+        .iter()
+        .filter(|link| !link.href().is_empty())
+        .collect();
+    if let Some(link) = links
+        .iter()
+        .find(|link| link.rel() == "alternate")
+        .or(links.first())
+    {
+        return link.href().to_string();
+    }
+
+    web_url(entry.id()).unwrap_or_default()
+}
+
+/// Picks the page an RSS item is about.
+///
+/// Prefers `<link>`, then an embedded `<atom:link>`, then a permalink guid that is a web URL.
+fn rss_link(item: &rss::Item) -> String {
+    if let Some(link) = item.link().filter(|link| !link.trim().is_empty()) {
+        return link.trim().to_string();
+    }
+
+    let atom_links = item.atom_ext().map(|atom| atom.links()).unwrap_or_default();
+    let atom_links: Vec<_> = atom_links
+        .iter()
+        .filter(|link| !link.href().is_empty())
+        .collect();
+    if let Some(link) = atom_links
+        .iter()
+        .find(|link| link.rel() == "alternate")
+        .or(atom_links.first())
+    {
+        return link.href().to_string();
+    }
+
+    item.guid()
+        .filter(|guid| guid.is_permalink())
+        .and_then(|guid| web_url(guid.value()))
+        .unwrap_or_default()
+}
+
+/// Returns the value as a URL if it is an `http` or `https` one.
+fn web_url(value: &str) -> Option<String> {
+    match Url::parse(value.trim()) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => Some(url.to_string()),
+        _ => None,
+    }
+}
+
+/// The date an Atom entry was last updated or published.
+///
+/// Falls back to RSS-style date elements from other namespaces,
+/// like `dc:date`, for feeds that mix the two.
+fn atom_date(entry: &atom_syndication::Entry) -> Option<DateTime<FixedOffset>> {
+    parse_feed_date(entry.updated())
+        .or_else(|| entry.published().and_then(parse_feed_date))
+        .or_else(|| {
+            entry
+                .extensions()
+                .values()
+                .flat_map(|elements| {
+                    ["date", "pubDate"]
+                        .into_iter()
+                        .filter_map(|name| elements.get(name))
+                })
+                .flatten()
+                .filter_map(|extension| extension.value())
+                .find_map(parse_feed_date)
+        })
+}
+
+/// Dates without an offset, which are taken to be in UTC.
+const NAIVE_DATE_FORMATS: &[&str] = &[
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M",
+];
+
+/// Parses a date from a feed, which should be RFC 2822 or RFC 3339 but often isn't quite.
+///
+/// Falls back to ISO 8601 without an offset and to bare dates, both read as UTC,
+/// and to RFC 2822 with a time zone abbreviation chrono doesn't know, also read as UTC.
+fn parse_feed_date(value: &str) -> Option<DateTime<FixedOffset>> {
+    let value = collapse_whitespace(value);
+    let value = value.as_str();
+
+    if let Ok(date) = DateTime::parse_from_rfc2822(value) {
+        return Some(date);
+    }
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Some(date);
+    }
+
+    for format in NAIVE_DATE_FORMATS {
+        if let Ok(date) = NaiveDateTime::parse_from_str(value, format) {
+            return Some(date.and_utc().fixed_offset());
+        }
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Some(date.and_time(NaiveTime::MIN).and_utc().fixed_offset());
+    }
+
+    // Zones like "CEST" or "IST" are ambiguous, so RFC 2822 parsing rejects them.
+    let (rest, zone) = value.rsplit_once(' ')?;
+    if zone.chars().all(|c| c.is_ascii_alphabetic()) {
+        return DateTime::parse_from_rfc2822(&format!("{rest} +0000")).ok();
+    }
+
+    None
+}
+
+/// The most characters of a summary to show in text output.
+pub(crate) const SUMMARY_LENGTH: usize = 240;
+
+/// Turns Atom text content into plain text according to its declared type.
+///
+/// Atom allows `text`, `html`, `xhtml`, or a MIME type, and defaults to `text`.
+fn atom_content_text(value: &str, content_type: Option<&str>) -> String {
+    match content_type.unwrap_or("text") {
+        "html" | "text/html" => strip_html(value),
+        // The parser hands xhtml content back as serialized markup, so the same treatment applies.
+        "xhtml" | "application/xhtml+xml" => strip_html(value),
+        _ => collapse_whitespace(value),
+    }
+}
+
+/// Reads the value of an extension element in an RSS item, like `itunes:episode`.
+///
+/// The parser takes the iTunes and Dublin Core elements out of the general extension map,
+/// so those are looked up under their usual `itunes` and `dc` prefixes.
+fn rss_extension_value(item: &rss::Item, prefix: &str, name: &str) -> Option<String> {
+    let extension = item
+        .extensions()
+        .get(prefix)
+        .and_then(|elements| elements.get(name))
+        .and_then(|values| values.first());
+    if let Some(value) = extension.and_then(|extension| extension.value()) {
+        return Some(value.trim().to_string());
+    }
+
+    let value = match prefix {
+        "itunes" => {
+            let itunes = item.itunes_ext()?;
+            match name {
+                "author" => itunes.author(),
+                "duration" => itunes.duration(),
+                "explicit" => itunes.explicit(),
+                "order" => itunes.order(),
+                "subtitle" => itunes.subtitle(),
+                "episode" => itunes.episode(),
+                "season" => itunes.season(),
+                "episodeType" => itunes.episode_type(),
+                _ => None,
+            }
+        }
+        "dc" => {
+            let dc = item.dublin_core_ext()?;
+            let values = match name {
+                "creator" => dc.creators(),
+                "date" => dc.dates(),
+                "identifier" => dc.identifiers(),
+                "subject" => dc.subjects(),
+                "title" => dc.titles(),
+                "type" => dc.types(),
+                _ => return None,
+            };
+            values.first().map(String::as_str)
+        }
+        _ => None,
+    };
+
+    value.map(|value| value.trim().to_string())
+}
+
+/// Extracts a plain-text summary from an Atom entry, preferring `<summary>` over `<content>`.
+fn atom_summary(entry: &atom_syndication::Entry) -> Option<String> {
+    // The parser doesn't keep the summary's type, so treat it as HTML, which is harmless for plain text.
+    let summary = match (entry.summary(), entry.content()) {
+        (Some(summary), _) => strip_html(summary),
+        (None, Some(content)) => atom_content_text(content.value()?, content.content_type()),
+        (None, None) => return None,
+    };
+
+    Some(summary).filter(|s| !s.is_empty())
+}
+
+/// Extracts a plain-text summary from an RSS item, preferring `<description>` over `<content:encoded>`.
+fn rss_summary(item: &rss::Item) -> Option<String> {
+    let summary = strip_html(item.description().or(item.content())?);
+
+    Some(summary).filter(|s| !s.is_empty())
+}
+
+/// Reduces an HTML fragment to its text, decoding entities and collapsing whitespace.
+fn strip_html(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+
+    collapse_whitespace(&decode_entities(&text))
+}
+
+/// Decodes the named entities common in feeds, and numeric character references.
+pub(crate) fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 10)
+            .map(|end| &rest[1..=end]);
+
+        let replacement = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        });
+
+        match (entity, replacement) {
+            (Some(entity), Some(c)) => {
+                decoded.push(c);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+pub(crate) fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Shortens text to at most `max` characters, ending with an ellipsis if anything was cut.
+pub(crate) fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+
+    let mut truncated: String = text.chars().take(max.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Finds the `href` of the first `<link rel="icon">` (or `rel="shortcut icon"`) tag in an HTML page.
+fn find_icon_link(html: &str) -> Option<&str> {
+    let lowercase = html.to_ascii_lowercase();
+    let mut rest = 0;
+
+    while let Some(start) = lowercase[rest..].find("<link") {
+        let start = rest + start;
+        let end = start + lowercase[start..].find('>')?;
+        let tag = &html[start..end];
+
+        let is_icon = html_attribute(tag, "rel").is_some_and(|rel| {
+            rel.split_ascii_whitespace()
+                .any(|r| r.eq_ignore_ascii_case("icon"))
+        });
+
+        if is_icon {
+            if let Some(href) = html_attribute(tag, "href") {
+                return Some(href);
+            }
+        }
+
+        rest = end;
+    }
+
+    None
+}
+
+/// Extracts the value of a quoted attribute from a single HTML tag.
+fn html_attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let lowercase = tag.to_ascii_lowercase();
+    let mut rest = 0;
+
+    while let Some(found) = lowercase[rest..].find(name) {
+        let found = rest + found;
+        rest = found + name.len();
+
+        let preceded_by_space = lowercase[..found].ends_with(|c: char| c.is_ascii_whitespace());
+        let value = lowercase[rest..].trim_start();
+        if !preceded_by_space || !value.starts_with('=') {
+            continue;
+        }
+
+        let value_start = tag.len() - value.len() + 1;
+        let value = tag[value_start..].trim_start();
+        let quote = value.chars().next()?;
+
+        return if quote == '"' || quote == '\'' {
+            value[1..].split(quote).next()
+        } else {
+            value
+                .split(|c: char| c.is_ascii_whitespace() || c == '>')
+                .next()
+        };
+    }
+
+    None
+}
+
+/// Resolves the favicon for a site, preferring a `<link rel="icon">` declared by its homepage
+/// and falling back to the conventional `/favicon.ico`.
+async fn resolve_favicon(client: &ClientWithMiddleware, site_url: &str) -> Option<String> {
+    let site_url = Url::parse(site_url).ok()?;
+
+    if let Ok(res) = client.get(site_url.clone()).send().await {
+        if res.status().is_success() {
+            if let Ok(html) = res.text().await {
+                if let Some(href) = find_icon_link(&html) {
+                    return site_url.join(href).ok().map(String::from);
+                }
+            }
+        }
+    }
+
+    let fallback = site_url.join("/favicon.ico").ok()?;
+    let res = client.get(fallback.clone()).send().await.ok()?;
+
+    res.status().is_success().then(|| fallback.to_string())
+}
+
+/// Finds the URL a feed gives for itself with `<link rel="self">`.
+pub(crate) fn self_link(body: &str) -> Option<String> {
+    match body.parse::<Feed>().ok()? {
+        Feed::Atom(feed) => feed
+            .links()
+            .iter()
+            .find(|link| link.rel() == "self")
+            .map(|link| link.href().to_string()),
+        Feed::RSS(channel) => channel
+            .atom_ext()?
+            .links()
+            .iter()
+            .find(|link| link.rel() == "self")
+            .map(|link| link.href().to_string()),
+    }
+}
+
+/// Compares URLs after parsing them, so that spelling differences like a
+/// host's case or a default port don't count.
+pub(crate) fn same_url(a: &str, b: &str) -> bool {
+    match (Url::parse(a), Url::parse(b)) {
+        (Ok(mut a), Ok(b)) => {
+            // Configured URLs may carry credentials that feeds never repeat.
+            let _ = a.set_username("");
+            let _ = a.set_password(None);
+            a == b
+        }
+        _ => a == b,
+    }
+}
+
+/// Finds the WebSub hubs a feed advertises with `<link rel="hub">`.
+///
+/// These could deliver updates as they happen, but for now they are only reported.
+pub(crate) fn websub_hubs(document: &Feed) -> Vec<String> {
+    match document {
+        Feed::Atom(feed) => feed
+            .links()
+            .iter()
+            .filter(|link| link.rel() == "hub")
+            .map(|link| link.href().to_string())
+            .collect(),
+        Feed::RSS(channel) => channel
+            .atom_ext()
+            .map(|atom| atom.links())
+            .unwrap_or_default()
+            .iter()
+            .filter(|link| link.rel() == "hub")
+            .map(|link| link.href().to_string())
+            .collect(),
+    }
+}
+
+/// The longest a feed's own update hint can keep --watch from fetching it again.
+const MAX_UPDATE_HINT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Reads how often an RSS feed says it changes, from `<ttl>` or the syndication module,
+/// up to [`MAX_UPDATE_HINT`].
+pub(crate) fn update_hint(channel: &rss::Channel) -> Option<Duration> {
+    let ttl = channel
+        .ttl()
+        .and_then(|minutes| minutes.trim().parse::<u64>().ok())
+        .map(|minutes| Duration::from_secs(minutes.saturating_mul(60)));
+
+    let period = channel.syndication_ext().map(|sy| {
+        let period_secs = match sy.period() {
+            UpdatePeriod::HOURLY => 60 * 60,
+            UpdatePeriod::DAILY => 24 * 60 * 60,
+            UpdatePeriod::WEEKLY => 7 * 24 * 60 * 60,
+            UpdatePeriod::MONTHLY => 30 * 24 * 60 * 60,
+            UpdatePeriod::YEARLY => 365 * 24 * 60 * 60,
+        };
+        Duration::from_secs(period_secs / u64::from(sy.frequency().max(1)))
+    });
+
+    ttl.or(period)
+        .filter(|hint| !hint.is_zero())
+        .map(|hint| hint.min(MAX_UPDATE_HINT))
+}
+
+/// One feed's items, with how long the feed says they will stay current.
+pub struct FeedBatch {
+    pub items: Vec<FeedItem>,
+    pub update_hint: Option<Duration>,
+    /// Whether the response came from the HTTP cache.
+    pub from_cache: bool,
+    /// What is worth telling about how fetching the feed went, for the caller to show.
+    pub notes: Vec<FetchNote>,
+}
+
+/// Choices about how [`fetch_feed`] goes about fetching.
+#[derive(Clone, Debug, Default)]
+pub struct FetchOptions {
+    /// Look up each feed's favicon for `feed_icon`.
+    pub resolve_favicons: bool,
+    /// Save each response body in this directory, as --dump-raw does.
+    pub dump_dir: Option<PathBuf>,
+    /// Try the usual feed paths on a bare domain that doesn't serve a feed, as --probe does.
+    pub probe: bool,
+    /// Scrape items out of feeds that don't parse, as --recover does.
+    pub recover: bool,
+    /// The prefix and name of an extension element to keep in each item's `sort_value`.
+    pub sort_extension: Option<(String, String)>,
+    /// Follow up to this many `rel="next"` links to later pages, as --follow-next does.
+    pub follow_next: usize,
+    /// How many items a feed needs before later pages are no longer worth fetching.
+    pub wanted_items: usize,
+}
+
+/// Where sites commonly serve their feed, tried in order by --probe.
+///
+/// `/feed.json` isn't among them, since JSON Feed documents can't be parsed.
+const PROBE_PATHS: &[&str] = &["/feed", "/rss.xml", "/atom.xml", "/index.xml"];
+
+/// Fetches and parses a feed's document, also saying whether it came from the HTTP cache
+/// along with any notes about fetching it.
+async fn fetch_document(
+    client: &ClientWithMiddleware,
+    feed: &FeedSpec,
+    options: &FetchOptions,
+) -> Result<(Feed, bool, Vec<FetchNote>), FetchError> {
+    let dump_to = options
+        .dump_dir
+        .as_ref()
+        .map(|dir| dir.join(dump_file_name(&display_url(&feed.url))));
+    let res = send_feed_request(client, feed).await?;
+    let from_cache = res
+        .headers()
+        .get(XCACHE)
+        .is_some_and(|value| value == "HIT");
+    let body = RawBody::read(res).await?;
+    let mut notes = vec![];
+
+    // The body is saved before anything is done to it.
+    if let Some(path) = dump_to {
+        if let Err(error) = fs::write(&path, &body.bytes) {
+            notes.push(FetchNote::DumpFailed { path, error });
+        }
+    }
+
+    let res = body.decode()?;
+
+    // Some servers flake out with a successful but empty response.
+    if res.trim().is_empty() {
+        return Err(FetchError::Empty);
+    }
+
+    match res.parse::<Feed>() {
+        Ok(document) => Ok((document, from_cache, notes)),
+        Err(error) => match options.recover.then(|| recover_feed(&res)).flatten() {
+            Some(channel) => {
+                notes.push(FetchNote::Recovered {
+                    feed: display_url(&feed.url),
+                    items: channel.items().len(),
+                });
+                Ok((Feed::RSS(channel), from_cache, notes))
+            }
+            None => Err(FetchError::Parse(error)),
+        },
+    }
+}
+
+/// Scrapes items out of a feed that isn't valid XML, for --recover.
+///
+/// Looks for `<item>` and `<entry>` blocks and picks the usual elements out of each,
+/// which copes with stray ampersands, unclosed tags, and the like.
+/// Returns `None` if nothing resembling an item turns up.
+fn recover_feed(body: &str) -> Option<rss::Channel> {
+    static BLOCK: LazyLock<Regex> = LazyLock::new(|| {
+        RegexBuilder::new(r"<(?:item|entry)\b[^>]*>(.*?)</(?:item|entry)\s*>")
+            .case_insensitive(true)
+            .dot_matches_new_line(true)
+            .build()
+            .expect("the item pattern is valid")
+    });
+    static ATOM_LINK: LazyLock<Regex> = LazyLock::new(|| {
+        RegexBuilder::new(r#"<link\b[^>]*\bhref\s*=\s*["']([^"']*)["']"#)
+            .case_insensitive(true)
+            .build()
+            .expect("the link pattern is valid")
+    });
+
+    let mut items = vec![];
+    for captures in BLOCK.captures_iter(body) {
+        let content = &captures[1];
+        let title = recovered_element(content, &["title"]);
+        let link = recovered_element(content, &["link"]).or_else(|| {
+            ATOM_LINK
+                .captures(content)
+                .map(|link| recovered_text(&link[1]))
+        });
+        if title.is_none() && link.is_none() {
+            continue;
+        }
+
+        let mut item = rss::Item::default();
+        item.set_title(title);
+        item.set_link(link);
+        item.set_pub_date(recovered_element(
+            content,
+            &["pubDate", "updated", "published", "dc:date"],
+        ));
+        item.set_description(recovered_element(
+            content,
+            &["description", "summary", "content"],
+        ));
+        item.set_guid(
+            recovered_element(content, &["guid", "id"]).map(|value| rss::Guid {
+                value,
+                permalink: false,
+            }),
+        );
+        items.push(item);
+    }
+    if items.is_empty() {
+        return None;
+    }
+
+    // The feed's own title comes before its first item.
+    let head = BLOCK
+        .find(body)
+        .map_or(body, |first| &body[..first.start()]);
+    let mut channel = rss::Channel::default();
+    channel.set_title(recovered_element(head, &["title"]).unwrap_or_default());
+    channel.set_items(items);
+    Some(channel)
+}
+
+/// The elements --recover picks out of scraped blocks, each with the pattern that finds it.
+static RECOVERED_ELEMENTS: LazyLock<HashMap<&str, Regex>> = LazyLock::new(|| {
+    [
+        "title",
+        "link",
+        "pubDate",
+        "updated",
+        "published",
+        "dc:date",
+        "description",
+        "summary",
+        "content",
+        "guid",
+        "id",
+    ]
+    .into_iter()
+    .map(|name| {
+        let element =
+            RegexBuilder::new(&format!(r"<{0}\b[^>]*>(.*?)</{0}\s*>", regex::escape(name)))
+                .case_insensitive(true)
+                .dot_matches_new_line(true)
+                .build()
+                .expect("element patterns are valid");
+        (name, element)
+    })
+    .collect()
+});
+
+/// The text of the first of the named elements found in a scraped block, if any isn't blank.
+///
+/// Names must be among [`RECOVERED_ELEMENTS`].
+fn recovered_element(content: &str, names: &[&str]) -> Option<String> {
+    names.iter().find_map(|name| {
+        RECOVERED_ELEMENTS[name]
+            .captures(content)
+            .map(|captures| recovered_text(&captures[1]))
+            .filter(|text| !text.is_empty())
+    })
+}
+
+/// Turns scraped element content into text, unwrapping CDATA and decoding entities where possible.
+fn recovered_text(raw: &str) -> String {
+    let raw = raw.trim();
+    if let Some(data) = raw
+        .strip_prefix("<![CDATA[")
+        .and_then(|raw| raw.strip_suffix("]]>"))
+    {
+        return data.trim().to_string();
+    }
+
+    // Broken entities are left as they are, since a broken feed may well have some.
+    decode_entities(raw).trim().to_string()
+}
+
+/// Fetches a feed's document, or with `probe`, the first of the usual feed paths on its site
+/// that has one if the configured URL is just a domain without a feed.
+async fn fetch_or_probe(
+    client: &ClientWithMiddleware,
+    feed: &FeedSpec,
+    options: &FetchOptions,
+) -> Result<(Feed, bool, Vec<FetchNote>), FetchError> {
+    let error = match fetch_document(client, feed, options).await {
+        Ok(document) => return Ok(document),
+        Err(error) => error,
+    };
+
+    let bare_domain = Url::parse(&feed.url)
+        .ok()
+        .filter(|url| matches!(url.path(), "" | "/") && url.query().is_none());
+    let Some(site) = bare_domain.filter(|_| options.probe) else {
+        return Err(error);
+    };
+
+    for path in PROBE_PATHS {
+        let Ok(url) = site.join(path) else {
+            continue;
+        };
+        let candidate = FeedSpec {
+            url: url.to_string(),
+            ..feed.clone()
+        };
+        if let Ok((document, from_cache, mut notes)) =
+            fetch_document(client, &candidate, options).await
+        {
+            notes.push(FetchNote::Probed {
+                feed: display_url(&feed.url),
+                url: display_url(&candidate.url),
+            });
+            return Ok((document, from_cache, notes));
+        }
+    }
+
+    Err(error)
+}
+
+/// Fetches one feed and turns its entries into items, in the order the feed lists them.
+pub async fn fetch_feed(
+    client: &ClientWithMiddleware,
+    feed: &FeedSpec,
+    feed_index: usize,
+    options: &FetchOptions,
+) -> Result<FeedBatch, FetchError> {
+    let (document, from_cache, mut notes) = fetch_or_probe(client, feed, options).await?;
+    let feed_icon = if options.resolve_favicons {
+        document_favicon(client, &document).await
+    } else {
+        None
+    };
+    let update_hint = match &document {
+        Feed::RSS(channel) => update_hint(channel),
+        Feed::Atom(_) => None,
+    };
+
+    let mut items = document_items(&document, feed, feed_index, feed_icon.clone(), options);
+    let mut next = next_page(&document, &feed.url);
+    let mut visited = vec![feed.url.clone()];
+    while visited.len() <= options.follow_next && items.len() < options.wanted_items {
+        // Some servers link the last page back to the first.
+        let Some(url) = next
+            .take()
+            .filter(|url| !visited.iter().any(|seen| same_url(seen, url)))
+        else {
+            break;
+        };
+        let page = FeedSpec {
+            url: url.clone(),
+            ..feed.clone()
+        };
+        match fetch_document(client, &page, options).await {
+            Ok((document, _, page_notes)) => {
+                notes.extend(page_notes);
+                items.extend(document_items(
+                    &document,
+                    feed,
+                    feed_index,
+                    feed_icon.clone(),
+                    options,
+                ));
+                next = next_page(&document, &url);
+            }
+            Err(error) => {
+                // The pages already fetched are still worth showing.
+                notes.push(FetchNote::NextPageFailed {
+                    feed: display_url(&feed.url),
+                    url: display_url(&url),
+                    error,
+                });
+            }
+        }
+        visited.push(url);
+    }
+
+    Ok(FeedBatch {
+        items,
+        update_hint,
+        from_cache,
+        notes,
+    })
+}
+
+/// Finds the page after this one of a feed split into pages, from its `<link rel="next">`.
+///
+/// Relative links are resolved against the URL the page was fetched from.
+fn next_page(document: &Feed, page_url: &str) -> Option<String> {
+    let href = match document {
+        Feed::Atom(feed) => feed
+            .links()
+            .iter()
+            .find(|link| link.rel() == "next")?
+            .href()
+            .to_string(),
+        Feed::RSS(channel) => channel
+            .atom_ext()?
+            .links()
+            .iter()
+            .find(|link| link.rel() == "next")?
+            .href()
+            .to_string(),
+    };
+
+    match Url::parse(page_url) {
+        Ok(base) => base.join(&href).ok().map(String::from),
+        Err(_) => Some(href),
+    }
+}
+
+/// Looks up the icon of the site a feed belongs to.
+async fn document_favicon(client: &ClientWithMiddleware, document: &Feed) -> Option<String> {
+    match document {
+        Feed::RSS(channel) => resolve_favicon(client, channel.link()).await,
+        Feed::Atom(feed) => {
+            if let Some(icon) = feed.icon() {
+                Some(icon.to_string())
+            } else if let Some(site) = feed.links().iter().find(|link| link.rel() == "alternate") {
+                resolve_favicon(client, site.href()).await
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// Turns a parsed feed's entries into items, in the order the feed lists them.
+///
+/// Entries without a date are left out, since there is nowhere to put them in the list.
+pub fn document_items(
+    document: &Feed,
+    feed: &FeedSpec,
+    feed_index: usize,
+    feed_icon: Option<String>,
+    options: &FetchOptions,
+) -> Vec<FeedItem> {
+    let feed_url = display_url(&feed.url);
+    let feed_hubs = websub_hubs(document);
+
+    let mut feed_items = vec![];
+    match document {
+        Feed::RSS(rss_feed) => {
+            for item in rss_feed.items() {
+                let dc_dates = item
+                    .dublin_core_ext()
+                    .map(|dc| dc.dates())
+                    .unwrap_or_default();
+                let Some(pub_date) = item
+                    .pub_date()
+                    .into_iter()
+                    .chain(dc_dates.iter().map(String::as_str))
+                    .find_map(parse_feed_date)
+                else {
+                    // Without a date there is nowhere to put it in the list.
+                    continue;
+                };
+
+                let title = item.title().unwrap_or("").to_string();
+                let link = rss_link(item);
+                let guid = item.guid().map(|guid| guid.value().to_string());
+                let feed_item = FeedItem {
+                    feed_title: feed.title(rss_feed.title()),
+                    feed_url: feed_url.clone(),
+                    feed_icon: feed_icon.clone(),
+                    feed_hubs: feed_hubs.clone(),
+                    group: feed.group.clone(),
+                    group_title: feed.group_title.clone(),
+                    id: stable_item_id(guid.as_deref(), &link, &title),
+                    title,
+                    link,
+                    pub_date,
+                    summary: rss_summary(item),
+                    tags: item
+                        .categories()
+                        .iter()
+                        .map(|category| category.name().to_string())
+                        .collect(),
+                    feed_index,
+                    priority: feed.priority,
+                    also_in: vec![],
+                    sort_value: options
+                        .sort_extension
+                        .as_ref()
+                        .and_then(|(prefix, name)| rss_extension_value(item, prefix, name)),
+                };
+
+                feed_items.push(feed_item);
+            }
+        }
+        Feed::Atom(atom_feed) => {
+            for item in atom_feed.entries() {
+                let Some(pub_date) = atom_date(item) else {
+                    continue;
+                };
+
+                let title = item.title().to_string();
+                let link = atom_link(item);
+                let feed_item = FeedItem {
+                    feed_title: feed.title(atom_title(atom_feed)),
+                    feed_url: feed_url.clone(),
+                    feed_icon: feed_icon.clone(),
+                    feed_hubs: feed_hubs.clone(),
+                    group: feed.group.clone(),
+                    group_title: feed.group_title.clone(),
+                    id: stable_item_id(Some(item.id()), &link, &title),
+                    title,
+                    link,
+                    pub_date,
+                    summary: atom_summary(item),
+                    tags: item
+                        .categories()
+                        .iter()
+                        .map(|category| category.term().to_string())
+                        .collect(),
+                    feed_index,
+                    priority: feed.priority,
+                    also_in: vec![],
+                    sort_value: options.sort_extension.as_ref().and_then(|(prefix, name)| {
+                        let extension = item.extensions().get(prefix)?.get(name)?.first()?;
+                        Some(extension.value()?.trim().to_string())
+                    }),
+                };
+
+                feed_items.push(feed_item);
+            }
+        }
+    }
+
+    feed_items
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Client;
+    use reqwest_middleware::ClientBuilder;
+
+    use super::*;
+
+    /// Wraps a body in a response, as if a server had sent it.
+    fn response(body: impl Into<Vec<u8>>) -> Response {
+        Response::from(hyper::Response::new(body.into()))
+    }
+
+    const RSS_FEED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>Example</title>
+    <link>https://example.com/</link>
+    <description>An example feed</description>
+    <item>
+      <title>First post</title>
+      <link>https://example.com/first</link>
+      <pubDate>Mon, 02 Jun 2025 12:00:00 GMT</pubDate>
+    </item>
+  </channel>
+</rss>"#;
+
+    #[tokio::test]
+    async fn feed_bodies_with_a_byte_order_mark_parse() {
+        for bom in ["\u{feff}", "\u{feff}\u{feff}"] {
+            let body = read_feed_body(response(format!("{bom}{RSS_FEED}")))
+                .await
+                .unwrap();
+            assert!(body.starts_with("<?xml"));
+
+            let document = body.parse::<Feed>().unwrap();
+            let feed = FeedSpec::new(&Url::parse("https://example.com/feed.xml").unwrap());
+            let items = document_items(&document, &feed, 0, None, &FetchOptions::default());
+            assert_eq!(items[0].title, "First post");
+        }
+    }
+
+    #[test]
+    fn feed_dates_parse_in_every_accepted_format() {
+        let cases = [
+            // RFC 2822, including obsolete zone names.
+            (
+                "Mon, 02 Jun 2025 12:00:00 +0200",
+                "2025-06-02T12:00:00+02:00",
+            ),
+            ("Mon, 02 Jun 2025 12:00:00 GMT", "2025-06-02T12:00:00+00:00"),
+            ("Mon, 02 Jun 2025 12:00:00 EST", "2025-06-02T12:00:00-05:00"),
+            (
+                "Mon,  02 Jun 2025\n 12:00:00 GMT",
+                "2025-06-02T12:00:00+00:00",
+            ),
+            // RFC 3339.
+            ("2025-06-02T12:00:00Z", "2025-06-02T12:00:00+00:00"),
+            (
+                "2025-06-02T12:00:00.250-04:00",
+                "2025-06-02T12:00:00.250-04:00",
+            ),
+            // ISO 8601 without an offset, read as UTC.
+            ("2025-06-02T12:00:00", "2025-06-02T12:00:00+00:00"),
+            ("2025-06-02 12:00:00.5", "2025-06-02T12:00:00.500+00:00"),
+            ("2025-06-02T12:00", "2025-06-02T12:00:00+00:00"),
+            ("2025-06-02 12:00", "2025-06-02T12:00:00+00:00"),
+            // Bare dates, at midnight UTC.
+            ("2025-06-02", "2025-06-02T00:00:00+00:00"),
+            // Zone abbreviations RFC 2822 doesn't define, read as UTC.
+            (
+                "Mon, 02 Jun 2025 12:00:00 CEST",
+                "2025-06-02T12:00:00+00:00",
+            ),
+        ];
+
+        for (value, expected) in cases {
+            assert_eq!(
+                parse_feed_date(value),
+                Some(DateTime::parse_from_rfc3339(expected).unwrap()),
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn feed_dates_that_are_not_dates_are_rejected() {
+        for value in [
+            "",
+            "yesterday",
+            "Mon, 02 Jun 2025 12:00:00 +99",
+            "2025-13-02",
+        ] {
+            assert_eq!(parse_feed_date(value), None, "{value}");
+        }
+    }
+
+    /// An Atom entry with an id and a date but no `<link>` at all.
+    fn linkless_entry(id: &str) -> atom_syndication::Entry {
+        let mut entry = atom_syndication::Entry::default();
+        entry.set_title("No links");
+        entry.set_id(id);
+        entry.set_updated("2025-06-02T12:00:00Z");
+        entry
+    }
+
+    #[test]
+    fn atom_entries_without_links_fall_back_to_their_id() {
+        let mut atom = atom_syndication::Feed::default();
+        atom.set_title("Example");
+        atom.set_entries(vec![
+            linkless_entry("https://example.com/posts/1"),
+            linkless_entry("urn:uuid:60a76c80-d399-11d9-b91C-0003939e0af6"),
+        ]);
+        let feed = FeedSpec::new(&Url::parse("https://example.com/feed.atom").unwrap());
+
+        let items = document_items(&Feed::Atom(atom), &feed, 0, None, &FetchOptions::default());
+
+        assert_eq!(items[0].link, "https://example.com/posts/1");
+        // An id that isn't a web page leaves the item without a link to show.
+        assert_eq!(items[1].link, "");
+    }
+
+    #[test]
+    fn items_carry_the_websub_hubs_of_their_feed() {
+        let body = r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Example</title>
+  <id>https://example.com/</id>
+  <updated>2025-06-02T12:00:00Z</updated>
+  <link rel="hub" href="https://pubsubhubbub.appspot.com/"/>
+  <entry>
+    <title>First post</title>
+    <id>https://example.com/first</id>
+    <updated>2025-06-02T12:00:00Z</updated>
+  </entry>
+</feed>"#;
+        let feed = FeedSpec::new(&Url::parse("https://example.com/feed.atom").unwrap());
+
+        let document = body.parse::<Feed>().unwrap();
+        let items = document_items(&document, &feed, 0, None, &FetchOptions::default());
+
+        assert_eq!(items[0].feed_hubs, ["https://pubsubhubbub.appspot.com/"]);
+        let json = serde_json::to_value(&items[0]).unwrap();
+        assert_eq!(json["feed_hubs"][0], "https://pubsubhubbub.appspot.com/");
+    }
+
+    #[test]
+    fn update_hints_are_capped_at_a_day() {
+        let channel = |ttl: &str| {
+            rss::ChannelBuilder::default()
+                .ttl(ttl.to_string())
+                .build()
+                .unwrap()
+        };
+
+        assert_eq!(
+            update_hint(&channel("30")),
+            Some(Duration::from_secs(30 * 60))
+        );
+        assert_eq!(update_hint(&channel("10080")), Some(MAX_UPDATE_HINT));
+        assert_eq!(
+            update_hint(&channel(&u64::MAX.to_string())),
+            Some(MAX_UPDATE_HINT)
+        );
+        assert_eq!(update_hint(&channel("0")), None);
+    }
+
+    #[tokio::test]
+    async fn empty_responses_are_reported_as_failures() {
+        let path = std::env::temp_dir().join(format!("dashboard-feeds-{}.xml", std::process::id()));
+        fs::write(&path, "  \n").unwrap();
+        let url = Url::from_file_path(&path).unwrap();
+        let client = ClientBuilder::new(Client::new()).build();
+
+        let result = fetch_feed(&client, &FeedSpec::new(&url), 0, &FetchOptions::default()).await;
+        fs::remove_file(&path).unwrap();
+
+        let error = result.err().unwrap();
+        assert!(matches!(error, FetchError::Empty));
+        let failure = FeedFailure::from_error(url.as_str(), &error);
+        assert_eq!(failure.category, "parse");
+    }
+
+    #[test]
+    fn atom_feeds_without_a_title_use_their_subtitle_then_their_host() {
+        let document = |head: &str| {
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  {head}
+  <id>https://example.com/</id>
+  <updated>2025-06-02T12:00:00Z</updated>
+  <entry>
+    <title>First post</title>
+    <id>https://example.com/first</id>
+    <updated>2025-06-02T12:00:00Z</updated>
+  </entry>
+</feed>"#
+            )
+            .parse::<Feed>()
+            .unwrap()
+        };
+        let feed = FeedSpec::new(&Url::parse("https://example.com/feed.atom").unwrap());
+        let feed_title = |document: &Feed, feed: &FeedSpec| {
+            document_items(document, feed, 0, None, &FetchOptions::default())[0]
+                .feed_title
+                .clone()
+        };
+
+        let subtitled = document("<title> </title><subtitle>Notes from Example</subtitle>");
+        assert_eq!(feed_title(&subtitled, &feed), "Notes from Example");
+
+        let untitled = document("");
+        assert_eq!(feed_title(&untitled, &feed), "example.com");
+
+        let named = FeedSpec {
+            name: Some("Example Notes".to_string()),
+            ..feed.clone()
+        };
+        assert_eq!(feed_title(&untitled, &named), "Example Notes");
+    }
+
+    #[test]
+    fn broken_feeds_are_recovered_item_by_item() {
+        let body = r#"<rss><channel><title>Broken & Co</title>
+<item><title>Fish &amp; chips</title><link>https://example.com/1</link>
+<pubDate>Mon, 02 Jun 2025 12:00:00 GMT</pubDate></item>
+<item><title><![CDATA[Unclosed <b>tag]]></title><link>https://example.com/2</link><p></item>
+</channel>"#;
+
+        let channel = recover_feed(body).unwrap();
+
+        assert_eq!(channel.title(), "Broken & Co");
+        let titles: Vec<_> = channel
+            .items()
+            .iter()
+            .filter_map(|item| item.title())
+            .collect();
+        assert_eq!(titles, ["Fish & chips", "Unclosed <b>tag"]);
+        assert_eq!(
+            channel.items()[0].pub_date(),
+            Some("Mon, 02 Jun 2025 12:00:00 GMT")
+        );
+    }
+
+    #[tokio::test]
+    async fn recovered_feeds_come_back_with_a_note_instead_of_printing_one() {
+        let path = std::env::temp_dir().join(format!(
+            "dashboard-feeds-recover-{}.xml",
+            std::process::id()
+        ));
+        fs::write(
+            &path,
+            "<rss><channel><item><title>Fish & chips</title>\
+             <pubDate>Mon, 02 Jun 2025 12:00:00 GMT</pubDate></item>",
+        )
+        .unwrap();
+        let url = Url::from_file_path(&path).unwrap();
+        let client = ClientBuilder::new(Client::new()).build();
+        let options = FetchOptions {
+            recover: true,
+            ..FetchOptions::default()
+        };
+
+        let result = fetch_feed(&client, &FeedSpec::new(&url), 0, &options).await;
+        fs::remove_file(&path).unwrap();
+
+        let batch = result.unwrap();
+        assert_eq!(batch.items.len(), 1);
+        assert!(matches!(
+            batch.notes.as_slice(),
+            [FetchNote::Recovered { items: 1, .. }]
+        ));
+        assert!(!batch.notes[0].is_warning());
+    }
+
+    /// The title, link, and date of each item a fixture feed turns into.
+    fn fixture_items(body: &str) -> Vec<(String, String, String)> {
+        let document = body.parse::<Feed>().unwrap();
+        let feed = FeedSpec::new(&Url::parse("https://example.com/feed.xml").unwrap());
+
+        document_items(&document, &feed, 0, None, &FetchOptions::default())
+            .into_iter()
+            .map(|item| (item.title, item.link, item.pub_date.to_rfc3339()))
+            .collect()
+    }
+
+    #[test]
+    fn rss_items_fall_back_to_atom_links_and_dublin_core_dates() {
+        let items = fixture_items(include_str!("../tests/fixtures/rss-with-atom-links.xml"));
+
+        let links: Vec<_> = items.iter().map(|(_, link, _)| link.as_str()).collect();
+        assert_eq!(
+            links,
+            [
+                "https://example.com/plain",
+                "https://example.com/atom-only",
+                "https://example.com/permalink"
+            ]
+        );
+        assert_eq!(items[2].2, "2025-05-31T12:00:00+00:00");
+    }
+
+    #[test]
+    fn atom_entries_fall_back_to_dublin_core_dates() {
+        let items = fixture_items(include_str!("../tests/fixtures/atom-with-rss-dates.xml"));
+
+        assert_eq!(items.len(), 2);
+        assert_eq!(
+            items[1],
+            (
+                "Dublin Core date".to_string(),
+                "https://example.com/dc-date".to_string(),
+                "2025-06-01T12:00:00+00:00".to_string()
+            )
+        );
+    }
+}
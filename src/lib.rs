@@ -0,0 +1,183 @@
+//! Fetches RSS and Atom feeds and merges their items into one list.
+//!
+//! The `dashboard-feeds` binary is a thin wrapper around [`run`]. Other programs can read
+//! their feeds with [`load_config`], fetch each [`FeedSpec`] with [`fetch_feed`], and combine
+//! the results with [`merge_and_sort`].
+
+mod cli;
+mod config;
+mod fetch;
+mod merge;
+mod render;
+
+pub use cli::{run, Args};
+pub use config::{
+    load_config, CacheModeArg, ConfigurationError, ConfigurationWarning, FeedSpec, Settings,
+};
+pub use fetch::{document_items, fetch_feed, FeedBatch, FetchError, FetchNote, FetchOptions};
+pub use merge::{merge_and_sort, DedupeKey, MergeOptions, SortOrder};
+pub use render::ThemeName;
+
+use std::{fs, io::Write, path::Path};
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use fetch::collapse_whitespace;
+use merge::normalize_link;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FeedItem {
+    pub feed_title: String,
+    pub feed_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feed_icon: Option<String>,
+    /// The WebSub hubs the item's feed advertises for pushing updates.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub feed_hubs: Vec<String>,
+    /// The name of the configuration group the item's feed is listed in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_title: Option<String>,
+    pub title: String,
+    pub link: String,
+    pub pub_date: DateTime<FixedOffset>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// With --cluster-by-title, the other feeds that had an item with this title.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub also_in: Vec<String>,
+    /// The item's guid or Atom id, or failing that a digest of its link and title,
+    /// so that downstream tools can tell items apart.
+    #[serde(default)]
+    pub id: String,
+    #[serde(skip)]
+    pub feed_index: usize,
+    #[serde(skip)]
+    pub priority: i64,
+    /// With --sort-by-extension, the value of that extension element in this item.
+    #[serde(skip)]
+    pub sort_value: Option<String>,
+}
+
+impl FeedItem {
+    /// The identity of this item for deduplication, if it has one.
+    fn dedupe_key(&self, key: DedupeKey) -> Option<String> {
+        let key = match key {
+            DedupeKey::Guid => match self.guid() {
+                Some(guid) => guid.to_string(),
+                None => normalize_link(&self.link),
+            },
+            DedupeKey::Link => normalize_link(&self.link),
+            DedupeKey::Title => collapse_whitespace(&self.title).to_lowercase(),
+        };
+
+        Some(key).filter(|k| !k.is_empty())
+    }
+
+    /// The item's own guid or Atom id, unless it had none and its `id` is a digest.
+    fn guid(&self) -> Option<&str> {
+        Some(self.id.as_str()).filter(|id| !id.is_empty() && !id.starts_with(DIGEST_ID_PREFIX))
+    }
+
+    /// A few hex digits identifying this item, for --with-id.
+    fn short_id(&self) -> String {
+        let digest = Sha256::digest(self.id.as_bytes());
+        digest[..4]
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+/// Starts the `id` of items that had no guid of their own.
+const DIGEST_ID_PREFIX: &str = "sha256:";
+
+/// The identifier of an item: its guid if it has a non-empty one,
+/// otherwise a digest of its link and title that stays the same between runs.
+pub(crate) fn stable_item_id(guid: Option<&str>, link: &str, title: &str) -> String {
+    if let Some(guid) = guid.filter(|guid| !guid.is_empty()) {
+        return guid.to_string();
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(link.as_bytes());
+    hasher.update([0]);
+    hasher.update(title.as_bytes());
+    let digest: String = hasher.finalize()[..16]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("{DIGEST_ID_PREFIX}{digest}")
+}
+
+/// Replaces the file at `path` by writing a temporary file next to it and renaming it into place.
+pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = path.with_file_name(format!(".{file_name}.{}.tmp", std::process::id()));
+
+    let result = (|| {
+        let mut file = fs::File::create(&temp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An item from a feed with nothing but a title and a publication time.
+    pub(crate) fn item(title: &str, pub_date: &str) -> FeedItem {
+        FeedItem {
+            feed_title: "Feed".to_string(),
+            feed_url: "https://example.com/feed.xml".to_string(),
+            feed_icon: None,
+            feed_hubs: vec![],
+            group: None,
+            group_title: None,
+            title: title.to_string(),
+            link: format!("https://example.com/{title}"),
+            pub_date: DateTime::parse_from_rfc3339(pub_date).unwrap(),
+            summary: None,
+            tags: vec![],
+            also_in: vec![],
+            id: title.to_string(),
+            feed_index: 0,
+            priority: 0,
+            sort_value: None,
+        }
+    }
+
+    #[test]
+    fn items_read_back_from_json_keep_their_guid() {
+        let mut guided = item("guided", "2025-06-01T10:00:00Z");
+        guided.id = "tag:example.com,2025:1".to_string();
+        let mut unguided = item("unguided", "2025-06-01T10:00:00Z");
+        unguided.id = stable_item_id(None, &unguided.link, &unguided.title);
+
+        let read_back = |item: &FeedItem| -> FeedItem {
+            serde_json::from_str(&serde_json::to_string(item).unwrap()).unwrap()
+        };
+
+        assert_eq!(
+            read_back(&guided).dedupe_key(DedupeKey::Guid).as_deref(),
+            Some("tag:example.com,2025:1")
+        );
+        assert_eq!(
+            read_back(&unguided).dedupe_key(DedupeKey::Guid).as_deref(),
+            Some("example.com/unguided")
+        );
+    }
+}
@@ -0,0 +1,620 @@
+//! Filtering, deduplicating, and ordering the items of many feeds.
+
+use std::{
+    borrow::Borrow,
+    cmp::{Ordering, Reverse},
+    collections::HashMap,
+    sync::Arc,
+};
+
+use chrono::{prelude::*, TimeDelta};
+use clap::ValueEnum;
+use owo_colors::OwoColorize;
+use reqwest::Url;
+
+use crate::{cli::ReadState, FeedItem};
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    /// By publication date
+    #[default]
+    Date,
+    /// By the feed's position in the configuration file, then by date
+    ConfigOrder,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum FeedSort {
+    /// Newest first, or oldest first with --first
+    Date,
+    /// In the order the feed lists them, for feeds that are curated rather than chronological
+    FeedOrder,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DedupeKey {
+    /// The item's RSS guid or Atom id, falling back to its link
+    Guid,
+    /// The item's link, ignoring scheme, fragment, and tracking parameters
+    Link,
+    /// The item's title, ignoring case and spacing
+    Title,
+}
+
+/// Decides which items are worth showing at all, before any limits apply.
+#[derive(Clone)]
+pub(crate) struct ItemFilter {
+    pub(crate) since: Option<DateTime<FixedOffset>>,
+    /// The --tag and --exclude-tag options, with their names lowercased.
+    pub(crate) include_tags: Vec<String>,
+    pub(crate) exclude_tags: Vec<String>,
+    /// Items already marked as read, unless --include-read was given.
+    pub(crate) read: Option<Arc<ReadState>>,
+    /// Feeds with fewer items than this left after filtering are left out entirely.
+    pub(crate) min_items: usize,
+    /// Whether to describe what happens to each item, for --explain.
+    pub(crate) explain: bool,
+}
+
+impl ItemFilter {
+    pub(crate) fn allows(&self, item: &FeedItem) -> bool {
+        self.rejection(item).is_none()
+    }
+
+    /// Says why an item is filtered out, or returns `None` if it isn't.
+    fn rejection(&self, item: &FeedItem) -> Option<String> {
+        let tags: Vec<String> = item.tags.iter().map(|tag| tag.to_lowercase()).collect();
+
+        if self.since.is_some_and(|since| item.pub_date < since) {
+            Some("published before --since".to_string())
+        } else if !self.include_tags.is_empty()
+            && !tags.iter().any(|tag| self.include_tags.contains(tag))
+        {
+            Some("has none of the --tag categories".to_string())
+        } else if let Some(tag) = tags.iter().find(|tag| self.exclude_tags.contains(tag)) {
+            Some(format!("has the --exclude-tag category \"{tag}\""))
+        } else if self.read.as_ref().is_some_and(|read| read.contains(item)) {
+            Some("already read".to_string())
+        } else {
+            None
+        }
+    }
+
+    /// Lists the filters an allowed item got through, for --explain.
+    pub(crate) fn passed(&self, item: &FeedItem) -> Vec<String> {
+        let mut passed = vec![];
+        if self.since.is_some() {
+            passed.push("published since --since".to_string());
+        }
+        if let Some(tag) = item
+            .tags
+            .iter()
+            .find(|tag| self.include_tags.contains(&tag.to_lowercase()))
+        {
+            passed.push(format!("has the --tag category \"{tag}\""));
+        }
+        if !self.exclude_tags.is_empty() {
+            passed.push("has no --exclude-tag category".to_string());
+        }
+        if self.read.is_some() {
+            passed.push("not read yet".to_string());
+        }
+
+        passed
+    }
+}
+
+/// Prints one line of --explain output about an item.
+pub(crate) fn explain(item: &FeedItem, verdict: &str, reason: &str) {
+    eprintln!(
+        "{} {verdict} {}: {}: {reason}",
+        "explain:".cyan().bold(),
+        item.feed_title,
+        item.title
+    );
+}
+
+/// Sorts items newest-first, or oldest-first if requested.
+pub(crate) fn sort_by_date<T: Borrow<FeedItem>>(items: &mut [T], oldest_first: bool) {
+    items.sort_by(|a, b| compare_by_date(a.borrow(), b.borrow(), oldest_first));
+}
+
+/// Orders items by date, with the higher priority feed first when dates are equal.
+fn compare_by_date(a: &FeedItem, b: &FeedItem, oldest_first: bool) -> Ordering {
+    let by_date = a.pub_date.cmp(&b.pub_date);
+    let by_date = if oldest_first {
+        by_date
+    } else {
+        by_date.reverse()
+    };
+
+    // The rest only make the order the same from run to run,
+    // whichever feed happened to arrive first.
+    by_date
+        .then(b.priority.cmp(&a.priority))
+        .then(a.feed_index.cmp(&b.feed_index))
+        .then_with(|| a.link.cmp(&b.link))
+        .then_with(|| a.title.cmp(&b.title))
+}
+
+/// Normalizes a link so that trivially different URLs for the same page compare equal.
+pub(crate) fn normalize_link(link: &str) -> String {
+    let Ok(mut url) = Url::parse(link.trim()) else {
+        return link.trim().to_string();
+    };
+
+    url.set_fragment(None);
+
+    let query: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(name, _)| !name.starts_with("utm_"))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    if query.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(query);
+    }
+
+    let host = url.host_str().unwrap_or_default();
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    let path = url.path().trim_end_matches('/');
+
+    match url.query() {
+        Some(query) => format!("{host}{path}?{query}"),
+        None => format!("{host}{path}"),
+    }
+}
+
+/// Folds items that share a title into the first of them, noting the other feeds in `also_in`.
+///
+/// Titles are compared ignoring case, punctuation, and spacing.
+pub(crate) fn cluster_by_title(items: &mut Vec<FeedItem>, explain_drops: bool) {
+    let title_key = |title: &str| {
+        title
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+            .collect::<String>()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase()
+    };
+
+    let mut clusters: HashMap<String, usize> = HashMap::new();
+    let mut kept: Vec<FeedItem> = Vec::with_capacity(items.len());
+    for item in items.drain(..) {
+        let key = title_key(&item.title);
+        if key.is_empty() {
+            kept.push(item);
+            continue;
+        }
+
+        match clusters.get(&key) {
+            Some(&index) => {
+                let first = &mut kept[index];
+                if explain_drops {
+                    explain(
+                        &item,
+                        "dropped",
+                        &format!("clustered into {}", first.feed_title),
+                    );
+                }
+                if first.feed_title != item.feed_title && !first.also_in.contains(&item.feed_title)
+                {
+                    first.also_in.push(item.feed_title);
+                }
+            }
+            None => {
+                clusters.insert(key, kept.len());
+                kept.push(item);
+            }
+        }
+    }
+
+    *items = kept;
+}
+
+/// Drops every item whose key was already seen earlier in the list.
+///
+/// With a `window`, only items published within that time of a kept item count as repeats.
+pub(crate) fn dedupe(
+    items: &mut Vec<FeedItem>,
+    key: DedupeKey,
+    window: Option<TimeDelta>,
+    explain_drops: bool,
+) {
+    // The date and feed of each item kept so far, by key.
+    let mut seen: HashMap<String, Vec<(DateTime<FixedOffset>, String)>> = HashMap::new();
+    items.retain(|item| {
+        let Some(key) = item.dedupe_key(key) else {
+            return true;
+        };
+
+        let kept = seen.entry(key).or_default();
+        let repeated = match window {
+            Some(window) => kept
+                .iter()
+                .find(|(date, _)| (item.pub_date - *date).abs() <= window),
+            None => kept.first(),
+        };
+        match repeated {
+            Some((_, feed_title)) => {
+                if explain_drops {
+                    explain(
+                        item,
+                        "dropped",
+                        &format!("repeats an item from {feed_title}"),
+                    );
+                }
+                false
+            }
+            None => {
+                kept.push((item.pub_date, item.feed_title.clone()));
+                true
+            }
+        }
+    });
+}
+
+/// Sorts merged items according to the requested order.
+pub(crate) fn sort_items(items: &mut [FeedItem], options: &MergeOptions) {
+    sort_by_date(items, options.oldest_first);
+
+    if options.by_extension {
+        // Decided once for all items, since comparing some pairs as numbers and
+        // others as text wouldn't give a consistent order.
+        let numeric = items
+            .iter()
+            .filter_map(|item| item.sort_value.as_deref())
+            .all(|value| value.parse::<f64>().is_ok());
+        let number = |value: &str| value.parse::<f64>().unwrap_or_default();
+
+        // Like dates, the largest value comes first unless --first is given.
+        items.sort_by(|a, b| match (&a.sort_value, &b.sort_value) {
+            (Some(a), Some(b)) => {
+                let order = if numeric {
+                    number(a).total_cmp(&number(b))
+                } else {
+                    a.cmp(b)
+                };
+                if options.oldest_first {
+                    order
+                } else {
+                    order.reverse()
+                }
+            }
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        });
+    }
+
+    // The sort is stable, so each feed's items stay in date order.
+    if options.sort == SortOrder::ConfigOrder {
+        items.sort_by_key(|f| (Reverse(f.priority), f.feed_index));
+    }
+}
+
+/// Merges per-feed batches round-robin, so that every feed is represented near the top.
+///
+/// Each batch is expected to already be in the order its items should be taken,
+/// which is by date unless --sort-within-feed says otherwise.
+/// Feeds take turns in the same order as their leading items,
+/// or in configuration order if that was requested.
+pub(crate) fn interleave(
+    mut batches: Vec<Vec<FeedItem>>,
+    sort: SortOrder,
+    oldest_first: bool,
+) -> Vec<FeedItem> {
+    match sort {
+        SortOrder::Date => batches.sort_by(|a, b| match (a.first(), b.first()) {
+            (Some(a), Some(b)) => compare_by_date(a, b, oldest_first),
+            (a, b) => b.is_some().cmp(&a.is_some()),
+        }),
+        SortOrder::ConfigOrder => {
+            batches.sort_by_key(|batch| batch.first().map(|f| (Reverse(f.priority), f.feed_index)))
+        }
+    }
+
+    let mut iters: Vec<_> = batches.into_iter().map(Vec::into_iter).collect();
+    let mut feed_items = vec![];
+
+    loop {
+        let before = feed_items.len();
+        feed_items.extend(iters.iter_mut().filter_map(Iterator::next));
+
+        if feed_items.len() == before {
+            return feed_items;
+        }
+    }
+}
+
+/// Choices about how [`merge_and_sort`] combines feeds' items.
+#[derive(Clone, Debug)]
+pub struct MergeOptions {
+    /// List the oldest items first, as --first does.
+    pub oldest_first: bool,
+    pub sort: SortOrder,
+    /// Order by each item's `sort_value` before its date, as --sort-by-extension does.
+    pub by_extension: bool,
+    /// Take items from each feed in turn, as --interleave does.
+    pub interleave: bool,
+    /// Keep only each feed's newest item, as --latest-per-feed does.
+    pub latest_per_feed: bool,
+    pub dedupe_by: Option<DedupeKey>,
+    /// Only count items as duplicates if they were published this close together.
+    pub dedupe_window: Option<TimeDelta>,
+    /// Show stories several feeds carry only once, as --cluster-by-title does.
+    pub cluster_by_title: bool,
+    /// Keep at most this many items from each configuration group, as --limit-per-group does.
+    pub limit_per_group: Option<usize>,
+    /// Describe each dropped item on stderr, as --explain does.
+    pub explain: bool,
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        Self {
+            oldest_first: false,
+            sort: SortOrder::Date,
+            by_extension: false,
+            interleave: false,
+            latest_per_feed: false,
+            dedupe_by: None,
+            dedupe_window: None,
+            cluster_by_title: false,
+            limit_per_group: None,
+            explain: false,
+        }
+    }
+}
+
+/// Combines every feed's items into one list, ordered and deduplicated as the options ask.
+pub fn merge_and_sort(feed_batches: Vec<Vec<FeedItem>>, options: &MergeOptions) -> Vec<FeedItem> {
+    let feed_batches = if options.latest_per_feed {
+        feed_batches
+            .into_iter()
+            .map(|batch| latest_item(batch, options.explain))
+            .collect()
+    } else {
+        feed_batches
+    };
+
+    let mut feed_items = if options.interleave {
+        interleave(feed_batches, options.sort, options.oldest_first)
+    } else {
+        let mut feed_items: Vec<FeedItem> = feed_batches.into_iter().flatten().collect();
+        sort_items(&mut feed_items, options);
+        feed_items
+    };
+
+    if let Some(key) = options.dedupe_by {
+        dedupe(&mut feed_items, key, options.dedupe_window, options.explain);
+    }
+    if options.cluster_by_title {
+        cluster_by_title(&mut feed_items, options.explain);
+    }
+    if let Some(limit) = options.limit_per_group {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        feed_items.retain(|item| {
+            let Some(group) = &item.group else {
+                return true;
+            };
+            let count = counts.entry(group.clone()).or_default();
+            *count += 1;
+            if *count > limit && options.explain {
+                explain(
+                    item,
+                    "dropped",
+                    &format!("past the --limit-per-group of {limit} in group \"{group}\""),
+                );
+            }
+            *count <= limit
+        });
+    }
+    feed_items
+}
+
+/// Keeps only the newest of one feed's items, for --latest-per-feed.
+fn latest_item(batch: Vec<FeedItem>, explain_drops: bool) -> Vec<FeedItem> {
+    // The first of several items published at the same time wins.
+    let Some(newest) = batch
+        .iter()
+        .enumerate()
+        .max_by_key(|(i, item)| (item.pub_date, Reverse(*i)))
+        .map(|(i, _)| i)
+    else {
+        return batch;
+    };
+
+    let mut latest = vec![];
+    for (i, item) in batch.into_iter().enumerate() {
+        if i == newest {
+            latest.push(item);
+        } else if explain_drops {
+            explain(&item, "dropped", "not the newest item in its feed");
+        }
+    }
+    latest
+}
+
+/// Filters and sorts one feed's items, keeping at most `limit` of them.
+pub(crate) fn trim_batch(
+    mut items: Vec<FeedItem>,
+    filter: &ItemFilter,
+    sort: FeedSort,
+    oldest_first: bool,
+    limit: usize,
+) -> Vec<FeedItem> {
+    items.retain(|f| match filter.rejection(f) {
+        Some(reason) if filter.explain => {
+            explain(f, "dropped", &reason);
+            false
+        }
+        rejection => rejection.is_none(),
+    });
+    if items.len() < filter.min_items {
+        if filter.explain {
+            let reason = format!("its feed has only {} items, under --min-items", items.len());
+            for item in &items {
+                explain(item, "dropped", &reason);
+            }
+        }
+        return vec![];
+    }
+    if sort == FeedSort::Date {
+        sort_by_date(&mut items, oldest_first);
+    }
+
+    if filter.explain {
+        for item in items.iter().skip(limit) {
+            explain(
+                item,
+                "dropped",
+                &format!("past the per-feed limit of {limit}"),
+            );
+        }
+    }
+    items.into_iter().take(limit).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::item;
+
+    #[test]
+    fn merging_orders_items_by_date() {
+        let batches = vec![
+            vec![
+                item("a2", "2025-06-02T10:00:00Z"),
+                item("a1", "2025-06-01T10:00:00Z"),
+            ],
+            vec![item("b1", "2025-06-01T12:00:00Z")],
+        ];
+
+        let titles =
+            |items: Vec<FeedItem>| items.into_iter().map(|item| item.title).collect::<Vec<_>>();
+        assert_eq!(
+            titles(merge_and_sort(batches.clone(), &MergeOptions::default())),
+            ["a2", "b1", "a1"]
+        );
+
+        let oldest_first = MergeOptions {
+            oldest_first: true,
+            ..MergeOptions::default()
+        };
+        assert_eq!(
+            titles(merge_and_sort(batches, &oldest_first)),
+            ["a1", "b1", "a2"]
+        );
+    }
+
+    #[test]
+    fn merging_can_follow_configuration_order() {
+        let mut second = item("second feed", "2025-06-02T10:00:00Z");
+        second.feed_index = 1;
+        let first = item("first feed", "2025-06-01T10:00:00Z");
+
+        let options = MergeOptions {
+            sort: SortOrder::ConfigOrder,
+            ..MergeOptions::default()
+        };
+        let merged = merge_and_sort(vec![vec![second], vec![first]], &options);
+        assert_eq!(merged[0].title, "first feed");
+    }
+
+    #[test]
+    fn merging_drops_duplicate_links() {
+        let mut story = item("story", "2025-06-01T10:00:00Z");
+        let mut repost = item("story again", "2025-06-02T10:00:00Z");
+        repost.link = "http://example.com/story?utm_source=feed".to_string();
+        story.link = "https://example.com/story".to_string();
+
+        let options = MergeOptions {
+            dedupe_by: Some(DedupeKey::Link),
+            ..MergeOptions::default()
+        };
+        let merged = merge_and_sort(vec![vec![story], vec![repost]], &options);
+        assert_eq!(merged.len(), 1);
+    }
+
+    #[test]
+    fn merging_can_keep_only_the_latest_item_per_feed() {
+        let batches = vec![
+            vec![
+                item("old", "2025-06-01T10:00:00Z"),
+                item("new", "2025-06-03T10:00:00Z"),
+            ],
+            vec![item("other", "2025-06-02T10:00:00Z")],
+        ];
+
+        let options = MergeOptions {
+            latest_per_feed: true,
+            ..MergeOptions::default()
+        };
+        let titles: Vec<String> = merge_and_sort(batches, &options)
+            .into_iter()
+            .map(|item| item.title)
+            .collect();
+        assert_eq!(titles, ["new", "other"]);
+    }
+
+    #[test]
+    fn merging_caps_each_configured_group() {
+        let mut items = vec![];
+        for (i, group) in [
+            Some("news"),
+            Some("news"),
+            Some("news"),
+            Some("work"),
+            None,
+            None,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut item = item(&format!("{i}"), &format!("2025-06-0{}T10:00:00Z", i + 1));
+            item.group = group.map(String::from);
+            items.push(item);
+        }
+
+        let options = MergeOptions {
+            limit_per_group: Some(1),
+            ..MergeOptions::default()
+        };
+        let merged = merge_and_sort(vec![items], &options);
+
+        let groups: Vec<_> = merged.iter().map(|item| item.group.as_deref()).collect();
+        // Newest first, so the last of the news items is the one kept.
+        assert_eq!(groups, [None, None, Some("work"), Some("news")]);
+        assert_eq!(merged[3].title, "2");
+    }
+
+    #[test]
+    fn extension_values_compare_as_numbers_only_when_all_are_numbers() {
+        let sorted = |values: &[&str]| {
+            let items = values
+                .iter()
+                .map(|&value| {
+                    let mut item = item(value, "2025-06-01T10:00:00Z");
+                    item.sort_value = Some(value.to_string());
+                    item
+                })
+                .collect();
+            let options = MergeOptions {
+                by_extension: true,
+                oldest_first: true,
+                ..MergeOptions::default()
+            };
+            merge_and_sort(vec![items], &options)
+                .into_iter()
+                .map(|item| item.title)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(sorted(&["10", "9", "2.5"]), ["2.5", "9", "10"]);
+        assert_eq!(sorted(&["10", "9", "b", "a"]), ["10", "9", "a", "b"]);
+    }
+}
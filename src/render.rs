@@ -0,0 +1,1050 @@
+//! Showing items as text, JSON, or through a template.
+
+use std::{fs, num::NonZeroUsize, path::Path};
+
+use chrono::{prelude::*, TimeDelta};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use handlebars::{Handlebars, RenderErrorReason};
+use miette::{bail, Context, Diagnostic, IntoDiagnostic, NamedSource, Result, SourceSpan};
+use owo_colors::{AnsiColors, OwoColorize, Style};
+use serde::Serialize;
+use supports_hyperlinks::supports_hyperlinks;
+use terminal_size::{terminal_size, Width};
+use textwrap::{core::display_width, fill, Options};
+use thiserror::Error;
+
+use crate::{
+    config::FeedSpec,
+    fetch::{display_url, truncate, FeedFailure, SUMMARY_LENGTH},
+    merge::sort_by_date,
+    FeedItem,
+};
+
+trait Hyperlink<S: AsRef<str>, T: AsRef<str>> {
+    fn hyperlink(&self, url: T) -> String;
+}
+
+impl<S: AsRef<str>, T: AsRef<str>> Hyperlink<S, T> for S {
+    fn hyperlink(&self, url: T) -> String {
+        format!(
+            "\u{1b}]8;;{}\u{1b}\\{}\u{1b}]8;;\u{1b}\\",
+            url.as_ref(),
+            self.as_ref()
+        )
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Format {
+    Text,
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum JsonShape {
+    /// A plain array of items
+    Array,
+    /// An object with "items" and "errors" arrays
+    Object,
+    /// An object mapping each feed's title to an array of its items
+    Grouped,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThemeName {
+    /// Dimmed feed names and details, with cyan tags
+    Default,
+    /// No colors, for terminals and logs that don't handle them
+    Mono,
+    /// Bright colors that stand out on dark backgrounds
+    Vivid,
+    /// Colors from the Solarized palette
+    Solarized,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum StyledField {
+    Date,
+    Source,
+    Title,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum HeaderStyle {
+    /// The theme's header style
+    Theme,
+    /// The theme's header style, underlined
+    Underline,
+    /// No styling, just the header text
+    Plain,
+}
+
+/// The styles text output is drawn with.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Theme {
+    /// Feed names in front of each item.
+    pub source: Style,
+    pub date: Style,
+    /// Item titles.
+    pub title: Style,
+    /// Less important details, like summaries, feed URLs, and cluster counts.
+    pub muted: Style,
+    /// Tags and sparklines.
+    pub highlight: Style,
+    /// Section and table headers.
+    pub header: Style,
+}
+
+impl Theme {
+    /// No styling at all, for output without colors.
+    pub fn plain() -> Self {
+        Theme {
+            source: Style::new(),
+            date: Style::new(),
+            title: Style::new(),
+            muted: Style::new(),
+            highlight: Style::new(),
+            header: Style::new(),
+        }
+    }
+
+    pub fn new(name: ThemeName) -> Self {
+        match name {
+            ThemeName::Default => Theme {
+                source: Style::new().dimmed(),
+                date: Style::new().dimmed(),
+                title: Style::new(),
+                muted: Style::new().dimmed(),
+                highlight: Style::new().cyan(),
+                header: Style::new().bold(),
+            },
+            ThemeName::Mono => Theme {
+                source: Style::new(),
+                date: Style::new(),
+                title: Style::new(),
+                muted: Style::new(),
+                highlight: Style::new(),
+                header: Style::new().bold(),
+            },
+            ThemeName::Vivid => Theme {
+                source: Style::new().bright_blue().bold(),
+                date: Style::new().bright_yellow(),
+                title: Style::new().bright_white(),
+                muted: Style::new().bright_magenta(),
+                highlight: Style::new().bright_cyan(),
+                header: Style::new().bright_green().bold().underline(),
+            },
+            ThemeName::Solarized => Theme {
+                source: Style::new().truecolor(0x26, 0x8b, 0xd2),
+                date: Style::new().truecolor(0x58, 0x6e, 0x75),
+                title: Style::new().truecolor(0x93, 0xa1, 0xa1),
+                muted: Style::new().truecolor(0x58, 0x6e, 0x75),
+                highlight: Style::new().truecolor(0x2a, 0xa1, 0x98),
+                header: Style::new().truecolor(0xb5, 0x89, 0x00).bold(),
+            },
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum GroupBy {
+    /// One section per feed
+    Feed,
+    /// One section per day of publication, in local time
+    Date,
+    /// One section per group of feeds in the configuration file
+    Group,
+}
+
+/// How much a feed has posted recently.
+#[derive(Serialize)]
+pub(crate) struct FeedStats {
+    pub(crate) feed_title: String,
+    feed_url: String,
+    pub(crate) last_day: usize,
+    pub(crate) last_week: usize,
+    pub(crate) last_month: usize,
+    /// Items posted on each of the last [`SPARKLINE_DAYS`] days, oldest first.
+    pub(crate) daily: Vec<usize>,
+    #[serde(skip)]
+    pub(crate) feed_index: usize,
+}
+
+/// How many days the --stats sparkline covers.
+pub(crate) const SPARKLINE_DAYS: usize = 14;
+
+impl FeedStats {
+    pub(crate) fn new(
+        feed_index: usize,
+        feed: &FeedSpec,
+        items: &[FeedItem],
+        now: DateTime<FixedOffset>,
+    ) -> Self {
+        let age = |item: &FeedItem| now.signed_duration_since(item.pub_date);
+        let within = |days| {
+            items
+                .iter()
+                .filter(|item| age(item) <= TimeDelta::days(days))
+                .count()
+        };
+
+        let mut daily = vec![0; SPARKLINE_DAYS];
+        for item in items {
+            let days_ago = age(item).num_days();
+            if (0..SPARKLINE_DAYS as i64).contains(&days_ago) {
+                daily[SPARKLINE_DAYS - 1 - days_ago as usize] += 1;
+            }
+        }
+
+        FeedStats {
+            feed_title: feed
+                .name
+                .clone()
+                .or_else(|| items.first().map(|item| item.feed_title.clone()))
+                .unwrap_or_else(|| display_url(&feed.url)),
+            feed_url: display_url(&feed.url),
+            last_day: within(1),
+            last_week: within(7),
+            last_month: within(30),
+            daily,
+            feed_index,
+        }
+    }
+}
+
+/// Draws counts as a row of block characters scaled to the largest count.
+pub(crate) fn sparkline(counts: &[usize]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+    let max = counts.iter().copied().max().unwrap_or(0);
+    counts
+        .iter()
+        .map(|&count| match count {
+            0 => ' ',
+            _ => BARS[(count - 1) * BARS.len() / max],
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+struct JsonOutput<'a> {
+    items: &'a [&'a FeedItem],
+    errors: &'a [FeedFailure],
+    /// Left out when no items are shown.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    range: Option<DateRange>,
+}
+
+/// The publication times of the oldest and newest items shown.
+#[derive(Serialize)]
+struct DateRange {
+    oldest: DateTime<FixedOffset>,
+    newest: DateTime<FixedOffset>,
+}
+
+impl DateRange {
+    fn of(items: &[&FeedItem]) -> Option<Self> {
+        Some(DateRange {
+            oldest: items.iter().map(|item| item.pub_date).min()?,
+            newest: items.iter().map(|item| item.pub_date).max()?,
+        })
+    }
+}
+
+/// Items grouped by feed title, serialized as an object in the order the feeds first appear.
+struct GroupedJson<'a>(Vec<(String, Vec<&'a FeedItem>)>);
+
+impl Serialize for GroupedJson<'_> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(title, items)| (title, items)))
+    }
+}
+
+/// Parses a --style value like `date=dimmed` or `source=bold #268bd2`.
+pub(crate) fn parse_field_style(value: &str) -> std::result::Result<(StyledField, Style), String> {
+    let (field, words) = value
+        .split_once('=')
+        .ok_or("expected a part and a style, like `date=dimmed`")?;
+    let field = StyledField::from_str(field.trim(), true)?;
+
+    let mut style = Style::new();
+    for word in words.split_whitespace() {
+        let word = word.to_lowercase();
+        style = match word.as_str() {
+            "plain" => Style::new(),
+            "bold" => style.bold(),
+            "dimmed" => style.dimmed(),
+            "italic" => style.italic(),
+            "underline" => style.underline(),
+            _ => match word.strip_prefix('#') {
+                Some(hex) => {
+                    let rgb = u32::from_str_radix(hex, 16)
+                        .ok()
+                        .filter(|_| hex.len() == 6)
+                        .ok_or(format!("`{word}` isn't a hex color like `#268bd2`"))?;
+                    let [_, r, g, b] = rgb.to_be_bytes();
+                    style.truecolor(r, g, b)
+                }
+                None => style.color(ansi_color(&word).ok_or(format!("unknown style `{word}`"))?),
+            },
+        };
+    }
+
+    Ok((field, style))
+}
+
+/// Looks up one of the terminal's named colors, like `cyan` or `bright-red`.
+fn ansi_color(name: &str) -> Option<AnsiColors> {
+    let (bright, name) = match name.strip_prefix("bright-") {
+        Some(name) => (true, name),
+        None => (false, name),
+    };
+
+    let color = match (name, bright) {
+        ("black", false) => AnsiColors::Black,
+        ("red", false) => AnsiColors::Red,
+        ("green", false) => AnsiColors::Green,
+        ("yellow", false) => AnsiColors::Yellow,
+        ("blue", false) => AnsiColors::Blue,
+        ("magenta", false) => AnsiColors::Magenta,
+        ("cyan", false) => AnsiColors::Cyan,
+        ("white", false) => AnsiColors::White,
+        ("black", true) => AnsiColors::BrightBlack,
+        ("red", true) => AnsiColors::BrightRed,
+        ("green", true) => AnsiColors::BrightGreen,
+        ("yellow", true) => AnsiColors::BrightYellow,
+        ("blue", true) => AnsiColors::BrightBlue,
+        ("magenta", true) => AnsiColors::BrightMagenta,
+        ("cyan", true) => AnsiColors::BrightCyan,
+        ("white", true) => AnsiColors::BrightWhite,
+        _ => return None,
+    };
+    Some(color)
+}
+
+/// Splits items into titled sections, keeping sections in order of their first item.
+fn group_items<'a>(
+    items: &[&'a FeedItem],
+    group_by: GroupBy,
+    oldest_first: bool,
+    now: DateTime<FixedOffset>,
+    tz: Option<Tz>,
+) -> Vec<(String, Vec<&'a FeedItem>)> {
+    let today = day_in_zone(now, tz);
+    let mut groups: Vec<(String, Vec<&FeedItem>)> = vec![];
+
+    for &item in items {
+        let header = group_header(item, group_by, today, tz);
+
+        match groups.iter_mut().find(|(h, _)| *h == header) {
+            Some((_, group)) => group.push(item),
+            None => groups.push((header, vec![item])),
+        }
+    }
+
+    if group_by == GroupBy::Date {
+        for (_, group) in groups.iter_mut() {
+            sort_by_date(group, oldest_first);
+        }
+    }
+
+    groups
+}
+
+/// The section header an item is listed under with --group-by.
+fn group_header(item: &FeedItem, group_by: GroupBy, today: NaiveDate, tz: Option<Tz>) -> String {
+    match group_by {
+        GroupBy::Feed => item.feed_title.clone(),
+        GroupBy::Group => item
+            .group_title
+            .clone()
+            .or_else(|| item.group.clone())
+            .unwrap_or_else(|| "Ungrouped".to_string()),
+        GroupBy::Date => {
+            let date = day_in_zone(item.pub_date, tz);
+
+            if date == today {
+                "Today".to_string()
+            } else if today.pred_opt() == Some(date) {
+                "Yesterday".to_string()
+            } else {
+                date.format("%A, %B %-d, %Y").to_string()
+            }
+        }
+    }
+}
+
+/// The day a time falls on in the --tz zone, or in local time without one,
+/// so that items are grouped under the same day their shown dates are in.
+fn day_in_zone(date: DateTime<FixedOffset>, tz: Option<Tz>) -> NaiveDate {
+    match tz {
+        Some(tz) => date.with_timezone(&tz).date_naive(),
+        None => date.with_timezone(&Local).date_naive(),
+    }
+}
+
+/// Shortens styled text to fit in `width` columns, ending with an ellipsis if anything was cut.
+///
+/// ANSI escape sequences take up no columns, and are kept so that styles still apply.
+fn truncate_to_width(text: &str, width: usize) -> String {
+    if display_width(text) <= width {
+        return text.to_string();
+    }
+
+    let mut truncated = String::new();
+    let mut used = 0;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Copy a whole `ESC [ ... final` sequence.
+            truncated.push(c);
+            if chars.peek() == Some(&'[') {
+                for c in chars.by_ref() {
+                    truncated.push(c);
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+            continue;
+        }
+
+        let char_width = display_width(c.encode_utf8(&mut [0; 4]));
+        if used + char_width > width.saturating_sub(1) {
+            break;
+        }
+        truncated.push(c);
+        used += char_width;
+    }
+
+    truncated.push('…');
+    // Styling cut off partway through would otherwise carry on past the line.
+    if truncated.contains('\x1b') {
+        truncated.push_str("\x1b[0m");
+    }
+    truncated
+}
+
+/// The width to wrap text output to.
+///
+/// Uses the terminal's width when stdout is one, then `$COLUMNS`, then 80 columns.
+pub(crate) fn output_width() -> usize {
+    let terminal_width = terminal_size().map(|(Width(width), _)| width);
+    width_from(terminal_width, std::env::var("COLUMNS").ok().as_deref())
+}
+
+/// Picks the output width from the terminal's width, if there is a terminal,
+/// and the value of `$COLUMNS`, if it is set.
+fn width_from(terminal_width: Option<u16>, columns: Option<&str>) -> usize {
+    if let Some(width) = terminal_width.filter(|&width| width > 0) {
+        return width.into();
+    }
+
+    columns
+        .and_then(|columns| columns.trim().parse().ok())
+        .filter(|&columns: &usize| columns > 0)
+        .unwrap_or(80)
+}
+
+/// Formats a publication time with --date-format, in the --tz zone or the local one.
+fn format_date(date: DateTime<FixedOffset>, options: &RenderOptions) -> String {
+    match options.tz {
+        Some(tz) => date
+            .with_timezone(&tz)
+            .format(&options.date_format)
+            .to_string(),
+        None => date
+            .with_timezone(&Local)
+            .format(&options.date_format)
+            .to_string(),
+    }
+}
+
+/// What separates items shown side by side with --items-per-line.
+const CELL_GAP: &str = "  ";
+
+/// Renders items as wrapped, hyperlinked lines for a terminal.
+fn render_text(shown: &[&FeedItem], options: &RenderOptions) -> String {
+    let theme = options.theme;
+    let width = options.width;
+    let title_wrap_options = Options::new(width)
+        .initial_indent("- ")
+        .subsequent_indent("    ");
+
+    let summary_wrap_options = Options::new(width)
+        .initial_indent("    ")
+        .subsequent_indent("    ");
+
+    // Items side by side are separated by a gap, and each gets an equal share of what's left.
+    let per_line = options.items_per_line.map_or(1, NonZeroUsize::get);
+    let cell_width = (width.saturating_sub(CELL_GAP.len() * (per_line - 1)) / per_line).max(1);
+
+    let render_item = |feed_item: &FeedItem, pad: bool| {
+        let mut feed_line = String::new();
+        if options.show_date {
+            let date = format_date(feed_item.pub_date, options);
+            feed_line.push_str(&format!("{} ", date.style(theme.date)));
+        }
+        feed_line.push_str(&format!(
+            "{}: {}",
+            feed_item.feed_title.style(theme.source),
+            feed_item.title.style(theme.title)
+        ));
+        if !feed_item.also_in.is_empty() {
+            feed_line.push_str(&format!(
+                " {}",
+                format!("[{} feeds]", feed_item.also_in.len() + 1).style(theme.muted)
+            ));
+        }
+        if options.show_url {
+            feed_line.push_str(&format!(
+                " {}",
+                format!("<{}>", feed_item.feed_url).style(theme.muted)
+            ));
+        }
+        if options.show_tags {
+            for tag in &feed_item.tags {
+                feed_line.push_str(&format!(" {}", format!("#{tag}").style(theme.highlight)));
+            }
+        }
+        if options.with_id {
+            feed_line.push_str(&format!(
+                " {}",
+                format!("[{}]", feed_item.short_id()).style(theme.muted)
+            ));
+        }
+        let feed_text = if options.compact {
+            truncate_to_width(&format!("- {feed_line}"), cell_width)
+        } else {
+            fill(&feed_line, &title_wrap_options)
+        };
+        // Measured before the link is added, since its escape sequence isn't all styling.
+        let padding = if pad {
+            " ".repeat(cell_width.saturating_sub(display_width(&feed_text)))
+        } else {
+            String::new()
+        };
+
+        let mut feed_link = if supports_hyperlinks() && !feed_item.link.is_empty() {
+            feed_text.hyperlink(&feed_item.link)
+        } else {
+            feed_text
+        };
+        feed_link.push_str(&padding);
+        if pad {
+            return feed_link;
+        }
+        feed_link.push('\n');
+
+        if let Some(summary) = feed_item
+            .summary
+            .as_ref()
+            .filter(|_| options.show_summary && !options.compact)
+        {
+            let summary = truncate(summary, SUMMARY_LENGTH);
+            feed_link.push_str(&format!(
+                "{}\n",
+                fill(&summary, &summary_wrap_options).style(theme.muted)
+            ));
+        }
+
+        feed_link
+    };
+    let render_items = |items: &[&FeedItem]| {
+        let mut output = String::new();
+        for row in items.chunks(per_line) {
+            let (last, rest) = row.split_last().expect("chunks are never empty");
+            for feed_item in rest {
+                output.push_str(&render_item(feed_item, true));
+                output.push_str(CELL_GAP);
+            }
+            output.push_str(&render_item(last, false));
+        }
+        output
+    };
+
+    let mut output = String::new();
+
+    match options.group_by {
+        Some(group_by) => {
+            for (i, (header, group)) in group_items(
+                shown,
+                group_by,
+                options.oldest_first,
+                options.now,
+                options.tz,
+            )
+            .into_iter()
+            .enumerate()
+            {
+                if i > 0 {
+                    output.push_str(&"\n".repeat(options.group_separator));
+                }
+                let header_style = match options.group_header_style {
+                    HeaderStyle::Underline if options.color => theme.header.underline(),
+                    HeaderStyle::Theme | HeaderStyle::Underline => theme.header,
+                    HeaderStyle::Plain => Style::new(),
+                };
+                output.push_str(&format!("{}\n", header.style(header_style)));
+                output.push_str(&render_items(&group));
+            }
+        }
+        None => output.push_str(&render_items(shown)),
+    }
+
+    output
+}
+
+/// Choices about how [`render`] formats items.
+#[derive(Clone, Debug)]
+pub(crate) struct RenderOptions {
+    pub format: Format,
+    /// Used in place of `format` when given, as --template-file does.
+    pub template: Option<Template>,
+    pub json_shape: JsonShape,
+    pub theme: Theme,
+    /// Whether styles beyond the theme's, like underlined headers, may be used.
+    pub color: bool,
+    /// The column text output is wrapped at.
+    pub width: usize,
+    /// Put each item's publication time in front of it, as --show-date does.
+    pub show_date: bool,
+    /// The strftime pattern for those times.
+    pub date_format: String,
+    /// The zone times and days are shown in, or local time without one.
+    pub tz: Option<Tz>,
+    pub show_url: bool,
+    pub show_tags: bool,
+    pub show_summary: bool,
+    /// Add a short hash of each item's id, as --with-id does.
+    pub with_id: bool,
+    /// Keep each item to one line, as --compact does.
+    pub compact: bool,
+    pub items_per_line: Option<NonZeroUsize>,
+    pub group_by: Option<GroupBy>,
+    /// Blank lines between --group-by sections.
+    pub group_separator: usize,
+    pub group_header_style: HeaderStyle,
+    /// List the oldest items first within date sections, as --first does.
+    pub oldest_first: bool,
+    /// The time "today" is counted from when grouping by date.
+    pub now: DateTime<FixedOffset>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            format: Format::Text,
+            template: None,
+            json_shape: JsonShape::Array,
+            theme: Theme::plain(),
+            color: false,
+            width: 80,
+            show_date: false,
+            date_format: "%Y-%m-%d %H:%M".to_string(),
+            tz: None,
+            show_url: false,
+            show_tags: false,
+            show_summary: false,
+            with_id: false,
+            compact: false,
+            items_per_line: None,
+            group_by: None,
+            group_separator: 1,
+            group_header_style: HeaderStyle::Theme,
+            oldest_first: false,
+            now: Utc::now().fixed_offset(),
+        }
+    }
+}
+
+/// Formats the items to show, and in JSON output any failures, as the options ask.
+pub(crate) fn render(
+    shown: &[&FeedItem],
+    failures: &[FeedFailure],
+    options: &RenderOptions,
+) -> Result<String> {
+    if let Some(template) = &options.template {
+        return template.render(shown, options);
+    }
+
+    match options.format {
+        Format::Text => Ok(render_text(shown, options)),
+        Format::Json => render_json(shown, failures, options),
+    }
+}
+
+/// Formats the items to show as JSON in the --json-shape asked for.
+pub(crate) fn render_json(
+    shown: &[&FeedItem],
+    failures: &[FeedFailure],
+    options: &RenderOptions,
+) -> Result<String> {
+    let json = match options.json_shape {
+        JsonShape::Array => serde_json::to_string_pretty(shown),
+        JsonShape::Object => serde_json::to_string_pretty(&JsonOutput {
+            items: shown,
+            errors: failures,
+            range: DateRange::of(shown),
+        }),
+        JsonShape::Grouped => serde_json::to_string_pretty(&GroupedJson(group_items(
+            shown,
+            GroupBy::Feed,
+            options.oldest_first,
+            options.now,
+            options.tz,
+        ))),
+    };
+
+    Ok(json.into_diagnostic()? + "\n")
+}
+
+#[derive(Error, Diagnostic, Debug)]
+enum TemplateError {
+    #[error("Unknown template field \"{name}\"")]
+    #[diagnostic(help(
+        "Each item has {}; count, the number of items, is outside {{{{#each items}}}}",
+        TEMPLATE_ITEM_FIELDS.join(", ")
+    ))]
+    UnknownField {
+        name: String,
+
+        #[source_code]
+        src: NamedSource<String>,
+
+        #[label("not a field here")]
+        span: SourceSpan,
+    },
+
+    #[error("Invalid template: {message}")]
+    Invalid {
+        message: String,
+
+        #[source_code]
+        src: NamedSource<String>,
+
+        #[label("here")]
+        span: SourceSpan,
+    },
+}
+
+/// The fields a --template-file can show for each item.
+const TEMPLATE_ITEM_FIELDS: &[&str] = &[
+    "title",
+    "link",
+    "feed_title",
+    "feed_url",
+    "date",
+    "pub_date",
+    "summary",
+    "tags",
+    "id",
+];
+
+/// What a --template-file is registered as with Handlebars.
+const TEMPLATE_NAME: &str = "template";
+
+/// A --template-file, checked and ready to fill in.
+#[derive(Clone)]
+pub(crate) struct Template {
+    source: String,
+    registry: Handlebars<'static>,
+}
+
+// Handlebars lists its helpers in no particular order, which would change the --digest-cache key.
+impl std::fmt::Debug for Template {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Template")
+            .field("source", &self.source)
+            .finish()
+    }
+}
+
+/// What a --template-file is filled in with.
+#[derive(Serialize)]
+struct TemplateData<'a> {
+    count: usize,
+    items: Vec<TemplateItem<'a>>,
+}
+
+/// An item as a --template-file sees it.
+#[derive(Serialize)]
+struct TemplateItem<'a> {
+    title: &'a str,
+    link: &'a str,
+    feed_title: &'a str,
+    feed_url: &'a str,
+    /// Laid out by --date-format.
+    date: String,
+    pub_date: String,
+    summary: &'a str,
+    tags: String,
+    id: &'a str,
+}
+
+impl<'a> TemplateItem<'a> {
+    fn new(item: &'a FeedItem, options: &RenderOptions) -> Self {
+        Self {
+            title: &item.title,
+            link: &item.link,
+            feed_title: &item.feed_title,
+            feed_url: &item.feed_url,
+            date: format_date(item.pub_date, options),
+            pub_date: item.pub_date.to_rfc3339(),
+            summary: item.summary.as_deref().unwrap_or(""),
+            tags: item.tags.join(", "),
+            id: &item.id,
+        }
+    }
+}
+
+impl Template {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Could not read template {}", path.display()))?;
+
+        Self::parse(&path.display().to_string(), content)
+    }
+
+    /// Parses a template, naming it `name` in any diagnostics.
+    ///
+    /// Fields that don't exist are caught here too, by filling the template in
+    /// with an example item.
+    pub fn parse(name: &str, content: String) -> Result<Self> {
+        let src = || NamedSource::new(name, content.clone());
+
+        let mut registry = Handlebars::new();
+        registry.set_strict_mode(true);
+        if let Err(error) = registry.register_template_string(TEMPLATE_NAME, &content) {
+            bail!(TemplateError::Invalid {
+                message: error.reason().to_string(),
+                src: src(),
+                span: template_span(&content, error.pos()),
+            });
+        }
+
+        let example = TemplateData {
+            count: 1,
+            items: vec![TemplateItem {
+                title: "Title",
+                link: "https://example.com/post",
+                feed_title: "Feed",
+                feed_url: "https://example.com/feed.xml",
+                date: "2025-06-02 12:00".to_string(),
+                pub_date: "2025-06-02T12:00:00+00:00".to_string(),
+                summary: "Summary",
+                tags: "tag".to_string(),
+                id: "id",
+            }],
+        };
+        if let Err(error) = registry.render(TEMPLATE_NAME, &example) {
+            let span = template_span(&content, error.line_no.zip(error.column_no));
+            match error.reason() {
+                RenderErrorReason::MissingVariable(Some(name)) => {
+                    bail!(TemplateError::UnknownField {
+                        name: name.clone(),
+                        src: src(),
+                        span,
+                    })
+                }
+                reason => bail!(TemplateError::Invalid {
+                    message: reason.to_string(),
+                    src: src(),
+                    span,
+                }),
+            }
+        }
+
+        Ok(Self {
+            source: content,
+            registry,
+        })
+    }
+
+    fn render(&self, items: &[&FeedItem], options: &RenderOptions) -> Result<String> {
+        let data = TemplateData {
+            count: items.len(),
+            items: items
+                .iter()
+                .map(|item| TemplateItem::new(item, options))
+                .collect(),
+        };
+
+        self.registry.render(TEMPLATE_NAME, &data).into_diagnostic()
+    }
+}
+
+/// Where in a template a Handlebars error points, given its line and column.
+///
+/// Errors at a tag cover the whole tag.
+fn template_span(content: &str, position: Option<(usize, usize)>) -> SourceSpan {
+    let Some((line, column)) = position else {
+        return (0, 0).into();
+    };
+
+    let offset = content
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum::<usize>()
+        + column.saturating_sub(1);
+    let offset = offset.min(content.len());
+    let length = content[offset..]
+        .strip_prefix("{{")
+        .and_then(|tag| tag.find("}}"))
+        .map_or(0, |end| end + 4);
+
+    (offset, length).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::item;
+
+    #[test]
+    fn date_groups_follow_the_display_time_zone() {
+        let tokyo: Tz = "Asia/Tokyo".parse().unwrap();
+        let now = DateTime::parse_from_rfc3339("2025-06-02T12:00:00Z").unwrap();
+        // Still June 1st in UTC, but already June 2nd in Tokyo.
+        let late = item("late", "2025-06-01T20:00:00Z");
+
+        let groups = group_items(&[&late], GroupBy::Date, false, now, Some(tokyo));
+        assert_eq!(groups[0].0, "Today");
+
+        let utc: Tz = "UTC".parse().unwrap();
+        let groups = group_items(&[&late], GroupBy::Date, false, now, Some(utc));
+        assert_eq!(groups[0].0, "Yesterday");
+    }
+
+    #[test]
+    fn output_width_without_a_terminal_falls_back_to_columns_then_80() {
+        assert_eq!(width_from(Some(132), Some("100")), 132);
+        assert_eq!(width_from(None, Some("100")), 100);
+        assert_eq!(width_from(Some(0), Some(" 100 ")), 100);
+        assert_eq!(width_from(None, Some("wide")), 80);
+        assert_eq!(width_from(None, Some("0")), 80);
+        assert_eq!(width_from(None, None), 80);
+    }
+
+    #[test]
+    fn group_sections_are_headed_by_the_group_title() {
+        let now = DateTime::parse_from_rfc3339("2025-06-02T12:00:00Z").unwrap();
+        let mut titled = item("titled", "2025-06-02T10:00:00Z");
+        titled.group = Some("news".to_string());
+        titled.group_title = Some("Tech News".to_string());
+        let mut untitled = item("untitled", "2025-06-02T09:00:00Z");
+        untitled.group = Some("hobbies".to_string());
+        let ungrouped = item("ungrouped", "2025-06-02T08:00:00Z");
+
+        let groups = group_items(
+            &[&titled, &untitled, &ungrouped],
+            GroupBy::Group,
+            false,
+            now,
+            None,
+        );
+
+        let headers: Vec<_> = groups.iter().map(|(header, _)| header.as_str()).collect();
+        assert_eq!(headers, ["Tech News", "hobbies", "Ungrouped"]);
+    }
+
+    #[test]
+    fn items_render_without_command_line_arguments() {
+        let first = item("First post", "2025-06-02T12:00:00Z");
+        let options = RenderOptions {
+            show_date: true,
+            tz: Some("UTC".parse().unwrap()),
+            ..RenderOptions::default()
+        };
+
+        let rendered = render(&[&first], &[], &options).unwrap();
+
+        assert!(rendered.starts_with("- 2025-06-02 12:00 Feed: First post"));
+    }
+
+    #[test]
+    fn templates_fill_in_each_item_between_a_header_and_footer() {
+        let template = Template::parse(
+            "digest.hbs",
+            "<h1>{{count}} new</h1>\n<ul>\n{{#each items}}\n  <li>{{title}} {{{title}}}{{#if summary}}: {{summary}}{{/if}} of {{@root.count}}</li>\n{{/each}}\n</ul>\n"
+                .to_string(),
+        )
+        .unwrap();
+        let mut first = item("Fish & <chips>", "2025-06-02T12:00:00Z");
+        first.summary = Some("Tasty".to_string());
+        let second = item("Peas", "2025-06-01T12:00:00Z");
+
+        let rendered = template
+            .render(&[&first, &second], &RenderOptions::default())
+            .unwrap();
+
+        // Block tags on their own line don't leave blank lines behind.
+        assert_eq!(
+            rendered,
+            "<h1>2 new</h1>\n<ul>\n  <li>Fish &amp; &lt;chips&gt; Fish & <chips>: Tasty of 2</li>\n  <li>Peas Peas of 2</li>\n</ul>\n"
+        );
+    }
+
+    #[test]
+    fn template_mistakes_are_reported_where_they_are() {
+        let error = |content: &str| {
+            Template::parse("digest.hbs", content.to_string())
+                .unwrap_err()
+                .downcast::<TemplateError>()
+                .unwrap()
+        };
+
+        match error("<ul>\n{{#each items}}\n  {{titel}}\n{{/each}}\n") {
+            TemplateError::UnknownField { name, span, .. } => {
+                assert_eq!(name, "titel");
+                assert_eq!((span.offset(), span.len()), (23, 9));
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+        // Item fields don't exist outside the items, nor the count inside them.
+        assert!(matches!(
+            error("{{title}}"),
+            TemplateError::UnknownField { .. }
+        ));
+        assert!(matches!(
+            error("{{#each items}}{{count}}{{/each}}"),
+            TemplateError::UnknownField { .. }
+        ));
+        // Unterminated tags, and blocks closed out of order.
+        assert!(matches!(
+            error("{{#each items}}{{title"),
+            TemplateError::Invalid { .. }
+        ));
+        assert!(matches!(
+            error("{{#each items}}{{#if summary}}{{/each}}"),
+            TemplateError::Invalid { .. }
+        ));
+        assert!(matches!(
+            error("{{#each items}}{{title}}"),
+            TemplateError::Invalid { .. }
+        ));
+    }
+
+    #[test]
+    fn compact_lines_without_color_are_cut_without_escape_sequences() {
+        let long = item(
+            "A title far too long to fit on one line",
+            "2025-06-02T12:00:00Z",
+        );
+        let options = RenderOptions {
+            compact: true,
+            width: 30,
+            ..RenderOptions::default()
+        };
+
+        let rendered = render(&[&long], &[], &options).unwrap();
+
+        assert_eq!(rendered.trim_end(), "- Feed: A title far too long …");
+    }
+}